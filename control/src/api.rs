@@ -0,0 +1,519 @@
+//! A local HTTP API with JSON bodies, for changing what's shown at runtime and seeing the result.
+//!
+//! - `GET`/`PUT`/`DELETE /color`: the colour set through the API, as an input with the configured
+//!   priority and an optional `timeout` in seconds. Getting it shows the colour being followed.
+//! - `GET`/`PUT /brightness`: `on` and `brightness`, from 0 to 255.
+//! - `GET`/`PUT /effect`: the `name` of the active effect and its `params`.
+//! - `GET /devices`: strips found on the network with mDNS.
+//! - `GET /frame`: the colour of every LED, as last sent.
+//! - `GET /frames`: a WebSocket pushing every frame as binary RGB data, the same as sent to the
+//!   strips, at the configured `stream_rate` at most.
+//! - `GET /layout`: the position of every LED, see [`Layout::points`].
+//! - `GET /`: a page drawing the frame stream, for a preview of the strip in the browser.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{net::TcpListener, time::interval};
+
+use crate::{
+    discovery::{self, Device},
+    effect,
+    layout::{Layout, Point},
+    ApiConfig, GlobalState, Rgb,
+};
+
+/// Name of the colour input set through the API, see [`crate::priority`].
+const SOURCE: &str = "api";
+
+/// The page served at `/`.
+const PREVIEW: &str = include_str!("../static/preview.html");
+
+#[derive(Clone)]
+struct Api {
+    priority: u8,
+    /// Time between frames pushed to stream clients.
+    stream_interval: Duration,
+    layout: Arc<Layout>,
+    global_state: Arc<Mutex<GlobalState>>,
+}
+
+/// A failed request, answered with the message in a JSON body.
+struct Error(StatusCode, String);
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn bad_request(message: String) -> Error {
+    Error(StatusCode::BAD_REQUEST, message)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Color {
+    #[serde(flatten)]
+    color: Rgb,
+    /// Input the colour is from, see [`crate::priority`].
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SetColor {
+    #[serde(flatten)]
+    color: Rgb,
+    timeout: Option<f32>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Brightness {
+    on: bool,
+    brightness: u8,
+}
+
+#[derive(Deserialize)]
+struct SetBrightness {
+    on: Option<bool>,
+    brightness: Option<u8>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Effect {
+    name: String,
+    params: BTreeMap<String, f32>,
+}
+
+#[derive(Deserialize)]
+struct SetEffect {
+    /// Effect to switch to, or the active one.
+    name: Option<String>,
+    /// Parameters of the effect to change, leaving the others as they are.
+    #[serde(default)]
+    params: HashMap<String, f32>,
+}
+
+/// Binds the listener to serve the API on, after checking the config.
+pub async fn bind(config: &ApiConfig) -> anyhow::Result<TcpListener> {
+    stream_interval(config)?;
+
+    Ok(TcpListener::bind(config.listen).await?)
+}
+
+fn stream_interval(config: &ApiConfig) -> anyhow::Result<Duration> {
+    Duration::try_from_secs_f32(1. / config.stream_rate)
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| anyhow!("Invalid stream_rate {}", config.stream_rate))
+}
+
+/// Serves the API on `listener` until it fails.
+pub async fn run(
+    config: ApiConfig,
+    listener: TcpListener,
+    layout: Arc<Layout>,
+    global_state: Arc<Mutex<GlobalState>>,
+) {
+    let api = Api {
+        priority: config.priority,
+        stream_interval: stream_interval(&config).expect("checked by bind"),
+        layout,
+        global_state,
+    };
+
+    if let Err(e) = axum::serve(listener, router(api)).await {
+        println!("API server failed: {e}");
+    }
+}
+
+fn router(api: Api) -> Router {
+    Router::new()
+        .route("/color", get(color).put(set_color).delete(clear_color))
+        .route("/brightness", get(brightness).put(set_brightness))
+        .route("/effect", get(effect).put(set_effect))
+        .route("/devices", get(devices))
+        .route("/frame", get(frame))
+        .route("/frames", get(frames))
+        .route("/layout", get(layout))
+        .route("/", get(|| async { Html(PREVIEW) }))
+        .with_state(api)
+}
+
+async fn color(State(api): State<Api>) -> Json<Color> {
+    let global_state = api.global_state.lock().unwrap();
+
+    Json(Color {
+        color: global_state.color(),
+        source: global_state
+            .priorities
+            .active()
+            .map(|(source, _)| source.to_string()),
+    })
+}
+
+async fn set_color(
+    State(api): State<Api>,
+    Json(body): Json<SetColor>,
+) -> Result<StatusCode, Error> {
+    let timeout = body
+        .timeout
+        .map(|timeout| {
+            Duration::try_from_secs_f32(timeout)
+                .map_err(|_| bad_request(format!("Invalid timeout {timeout}")))
+        })
+        .transpose()?;
+
+    api.global_state
+        .lock()
+        .unwrap()
+        .priorities
+        .set(SOURCE, api.priority, body.color, timeout);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn clear_color(State(api): State<Api>) -> StatusCode {
+    api.global_state.lock().unwrap().priorities.clear(SOURCE);
+
+    StatusCode::NO_CONTENT
+}
+
+async fn brightness(State(api): State<Api>) -> Json<Brightness> {
+    let global_state = api.global_state.lock().unwrap();
+
+    Json(Brightness {
+        on: global_state.on,
+        brightness: global_state.brightness,
+    })
+}
+
+async fn set_brightness(State(api): State<Api>, Json(body): Json<SetBrightness>) -> StatusCode {
+    let mut global_state = api.global_state.lock().unwrap();
+
+    if let Some(on) = body.on {
+        global_state.on = on;
+    }
+
+    if let Some(brightness) = body.brightness {
+        global_state.brightness = brightness;
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+async fn effect(State(api): State<Api>) -> Json<Effect> {
+    let global_state = api.global_state.lock().unwrap();
+    let name = &global_state.effect;
+
+    Json(Effect {
+        name: name.clone(),
+        params: effect::params(name)
+            .iter()
+            .map(|&param| (param.name.to_string(), global_state.param(name, param)))
+            .collect(),
+    })
+}
+
+async fn set_effect(
+    State(api): State<Api>,
+    Json(body): Json<SetEffect>,
+) -> Result<StatusCode, Error> {
+    let mut global_state = api.global_state.lock().unwrap();
+
+    let name = body.name.unwrap_or_else(|| global_state.effect.clone());
+    if effect::find(&name).is_none() {
+        return Err(bad_request(format!(
+            "Unknown effect {name:?}, expected one of {:?}",
+            effect::names()
+        )));
+    }
+
+    let known = effect::params(&name);
+    for (param, value) in &body.params {
+        if !known.iter().any(|known| known.name == param) {
+            return Err(bad_request(format!("{name} has no parameter {param:?}")));
+        }
+
+        if !value.is_finite() {
+            return Err(bad_request(format!("Invalid value {value} for {param}")));
+        }
+    }
+
+    global_state
+        .params
+        .entry(name.clone())
+        .or_default()
+        .extend(body.params);
+    global_state.effect = name;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn devices() -> Result<Json<Vec<Device>>, Error> {
+    discovery::find(None)
+        .await
+        .map(Json)
+        .map_err(|e| Error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn frame(State(api): State<Api>) -> Json<Vec<Rgb>> {
+    Json(api.global_state.lock().unwrap().frame.clone())
+}
+
+async fn frames(State(api): State<Api>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| stream_frames(api, socket))
+}
+
+/// Pushes the frame to `socket` whenever it changed, until the client goes away.
+async fn stream_frames(api: Api, mut socket: WebSocket) {
+    let mut interval = interval(api.stream_interval);
+    let mut sent = Vec::new();
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let buf = api
+                    .global_state
+                    .lock()
+                    .unwrap()
+                    .frame
+                    .iter()
+                    .copied()
+                    .flat_map(<[u8; 3]>::from)
+                    .collect::<Vec<u8>>();
+
+                if buf != sent {
+                    if socket.send(Message::Binary(buf.clone())).await.is_err() {
+                        return;
+                    }
+                    sent = buf;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn layout(State(api): State<Api>) -> Json<Vec<Point>> {
+    Json(api.layout.points().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use reqwest::Client;
+    use serde_json::Value;
+    use tokio_tungstenite::{connect_async, tungstenite};
+
+    use super::*;
+    use crate::layout::LayoutConfig;
+
+    const RED: Rgb = Rgb { r: 255, g: 0, b: 0 };
+    const BLUE: Rgb = Rgb { r: 0, g: 0, b: 255 };
+
+    /// Serves the API on a free port, returning its URL.
+    async fn serve(global_state: &Arc<Mutex<GlobalState>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let config = ApiConfig {
+            listen: listener.local_addr().unwrap(),
+            priority: 150,
+            stream_rate: 100.,
+        };
+        let layout = Layout::new(&LayoutConfig::Linear, 2).unwrap();
+        tokio::spawn(run(
+            config,
+            listener,
+            Arc::new(layout),
+            global_state.clone(),
+        ));
+
+        url
+    }
+
+    #[tokio::test]
+    async fn sets_colour_above_other_inputs() {
+        let global_state = Arc::new(Mutex::new(GlobalState::new("solid", &[])));
+        global_state
+            .lock()
+            .unwrap()
+            .priorities
+            .set("home_assistant", 100, BLUE, None);
+
+        let url = serve(&global_state).await;
+        let client = Client::new();
+
+        let response = client
+            .put(format!("{url}/color"))
+            .json(&json!({ "r": 255, "g": 0, "b": 0 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let color: Color = client
+            .get(format!("{url}/color"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            color,
+            Color {
+                color: RED,
+                source: Some("api".to_string()),
+            }
+        );
+
+        client.delete(format!("{url}/color")).send().await.unwrap();
+        assert_eq!(global_state.lock().unwrap().color(), BLUE);
+
+        let response = client
+            .put(format!("{url}/color"))
+            .json(&json!({ "r": 255, "g": 0, "b": 0, "timeout": -1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn sets_brightness_and_effect() {
+        let global_state = Arc::new(Mutex::new(GlobalState::new("solid", &[])));
+        let url = serve(&global_state).await;
+        let client = Client::new();
+
+        client
+            .put(format!("{url}/brightness"))
+            .json(&json!({ "brightness": 10 }))
+            .send()
+            .await
+            .unwrap();
+        let brightness: Brightness = client
+            .get(format!("{url}/brightness"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            brightness,
+            Brightness {
+                on: true,
+                brightness: 10
+            }
+        );
+
+        let response = client
+            .put(format!("{url}/effect"))
+            .json(&json!({ "name": "wave", "params": { "speed": 2 } }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let effect: Effect = client
+            .get(format!("{url}/effect"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            effect,
+            Effect {
+                name: "wave".to_string(),
+                params: BTreeMap::from([("bands".to_string(), 2.), ("speed".to_string(), 2.)]),
+            }
+        );
+
+        for body in [
+            json!({ "name": "disco" }),
+            json!({ "params": { "density": 1 } }),
+        ] {
+            let response = client
+                .put(format!("{url}/effect"))
+                .json(&body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(global_state.lock().unwrap().effect, "wave");
+    }
+
+    #[tokio::test]
+    async fn serves_the_frame() {
+        let global_state = Arc::new(Mutex::new(GlobalState::new("solid", &[])));
+        global_state.lock().unwrap().frame = vec![RED, BLUE];
+
+        let url = serve(&global_state).await;
+        let frame: Vec<Rgb> = reqwest::get(format!("{url}/frame"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(frame, [RED, BLUE]);
+
+        let layout: Value = reqwest::get(format!("{url}/layout"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(layout[1], json!({ "x": 1., "y": 0., "z": 0. }));
+
+        let preview = reqwest::get(&url).await.unwrap().text().await.unwrap();
+        assert!(preview.contains("new WebSocket"));
+    }
+
+    #[tokio::test]
+    async fn streams_changed_frames() {
+        let global_state = Arc::new(Mutex::new(GlobalState::new("solid", &[])));
+        global_state.lock().unwrap().frame = vec![RED, BLUE];
+
+        let url = serve(&global_state).await;
+        let (mut socket, _) = connect_async(format!("{}/frames", url.replace("http", "ws")))
+            .await
+            .unwrap();
+
+        let message = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            message,
+            tungstenite::Message::Binary(vec![255, 0, 0, 0, 0, 255])
+        );
+
+        global_state.lock().unwrap().frame = vec![BLUE, BLUE];
+        let message = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            message,
+            tungstenite::Message::Binary(vec![0, 0, 255, 0, 0, 255])
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// Exponentially growing retry delays with random jitter, so a flaky server isn't hammered and
+/// several clients don't retry in lockstep.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Starts over from the minimum delay, e.g. after a successful connection.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+
+    /// The delay before the next attempt, somewhere between half and all of the current step.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current.mul_f32(rand::random::<f32>() * 0.5 + 0.5);
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn grows_up_to_the_cap() {
+        let mut backoff = Backoff::new(MIN, MAX);
+
+        for step in [1, 2, 4, 8, 10, 10] {
+            let step = Duration::from_secs(step);
+            let delay = backoff.next_delay();
+            assert!(delay >= step / 2 && delay <= step, "{delay:?} for {step:?}");
+        }
+    }
+
+    #[test]
+    fn resets_to_the_minimum() {
+        let mut backoff = Backoff::new(MIN, MAX);
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= MIN);
+        assert!(backoff.next_delay() <= MIN * 2);
+    }
+}
@@ -0,0 +1,138 @@
+//! Receiving frames from lighting software over E1.31 or Art-Net, to pass on to the strip.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use home_leds_protocol::{
+    artnet,
+    dmx::{FrameTracker, UniverseMap},
+    e131, PixelFormat,
+};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use crate::{color::Rgb, BridgeConfig, GlobalState};
+
+/// How long a received frame is shown for, before going back to the effect.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    E131,
+    ArtNet,
+}
+
+impl Protocol {
+    fn port(self) -> u16 {
+        match self {
+            Protocol::E131 => e131::PORT,
+            Protocol::ArtNet => artnet::PORT,
+        }
+    }
+}
+
+/// The last complete frame received.
+pub struct Frame {
+    pub pixels: Vec<Rgb>,
+    pub received: Instant,
+}
+
+impl Frame {
+    pub fn is_fresh(&self) -> bool {
+        self.received.elapsed() < TIMEOUT
+    }
+}
+
+fn universe_map(config: &BridgeConfig) -> UniverseMap {
+    let start_universe = config.start_universe.unwrap_or(match config.protocol {
+        Protocol::E131 => e131::FIRST_UNIVERSE,
+        Protocol::ArtNet => artnet::FIRST_UNIVERSE,
+    });
+
+    UniverseMap {
+        start_universe,
+        start_channel: config.start_channel,
+        channels_per_universe: config.channels_per_universe,
+        format: PixelFormat::Rgb8,
+    }
+}
+
+/// Binds the socket to listen on, joining the multicast groups of E1.31 universes.
+pub async fn bind(config: &BridgeConfig, leds: usize) -> anyhow::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::from((
+        Ipv4Addr::UNSPECIFIED,
+        config.protocol.port(),
+    )))
+    .await?;
+
+    if let Protocol::E131 = config.protocol {
+        for universe in universe_map(config).universes(leds) {
+            let group = Ipv4Addr::from(e131::multicast_address(universe));
+            socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+        }
+    }
+
+    Ok(socket)
+}
+
+pub async fn run(
+    config: BridgeConfig,
+    socket: UdpSocket,
+    leds: usize,
+    global_state: Arc<Mutex<GlobalState>>,
+) {
+    let map = universe_map(&config);
+    let mut frames = FrameTracker::default();
+    let mut pixels = vec![Rgb::BLACK; leds];
+
+    let mut buf = [0; 1500];
+
+    loop {
+        let n = match socket.recv(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                println!("Failed to receive bridge packet: {e}");
+                continue;
+            }
+        };
+
+        let (universe, data) = match config.protocol {
+            Protocol::E131 => match e131::Packet::decode(&buf[..n]) {
+                Ok(packet)
+                    if packet.options
+                        & (e131::OPTION_PREVIEW_DATA | e131::OPTION_STREAM_TERMINATED)
+                        == 0 =>
+                {
+                    (packet.universe, packet.data)
+                }
+                _ => continue,
+            },
+            Protocol::ArtNet => match artnet::Packet::decode(&buf[..n]) {
+                Ok(packet) => (packet.universe, packet.data),
+                Err(_) => continue,
+            },
+        };
+
+        // Universes not on the strip don't count towards frames either
+        let Some(offset) = map.offset(universe, leds) else {
+            continue;
+        };
+
+        let received = map.format.rgb_pixels(map.pixel_data(data));
+        for (led, rgb) in pixels.iter_mut().skip(offset).zip(received) {
+            *led = Rgb::from(rgb);
+        }
+
+        if frames.receive(universe) {
+            global_state.lock().unwrap().bridge_frame = Some(Frame {
+                pixels: pixels.clone(),
+                received: Instant::now(),
+            });
+        }
+    }
+}
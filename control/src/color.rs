@@ -0,0 +1,189 @@
+use std::ops::Mul;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const WHITE: Rgb = Rgb {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    pub const BLACK: Rgb = Rgb { r: 0, g: 0, b: 0 };
+
+    #[allow(dead_code)]
+    pub const fn grey(value: u8) -> Rgb {
+        Rgb {
+            r: value,
+            g: value,
+            b: value,
+        }
+    }
+
+    /// Converts a hue (0-360°) and saturation (0-100%) at full value, as used by Home Assistant's
+    /// `hs_color`.
+    pub fn from_hs(hue: f32, saturation: f32) -> Rgb {
+        let h = hue.rem_euclid(360.) / 60.;
+        let s = (saturation / 100.).clamp(0., 1.);
+        let f = h.fract();
+
+        let (p, q, t) = (1. - s, 1. - s * f, 1. - s * (1. - f));
+        let (r, g, b) = match h as u8 {
+            0 => (1., t, p),
+            1 => (q, 1., p),
+            2 => (p, 1., t),
+            3 => (p, q, 1.),
+            4 => (t, p, 1.),
+            _ => (1., p, q),
+        };
+
+        Rgb::from_linear(r, g, b)
+    }
+
+    /// Converts a CIE 1931 chromaticity at full brightness, as used by Home Assistant's
+    /// `xy_color`.
+    pub fn from_xy(x: f32, y: f32) -> Rgb {
+        if y <= 0. {
+            return Rgb::BLACK;
+        }
+
+        let big_x = x / y;
+        let big_z = (1. - x - y) / y;
+
+        // Wide gamut D65 conversion, matching Home Assistant's `color_xy_brightness_to_RGB`
+        let r = big_x * 1.656492 - 0.354851 - big_z * 0.255038;
+        let g = -big_x * 0.707196 + 1.655397 + big_z * 0.036152;
+        let b = big_x * 0.051713 - 0.121364 + big_z * 1.01153;
+
+        let gamma = |v: f32| {
+            if v <= 0.0031308 {
+                12.92 * v
+            } else {
+                1.055 * v.powf(1. / 2.4) - 0.055
+            }
+        };
+        let (r, g, b) = (gamma(r).max(0.), gamma(g).max(0.), gamma(b).max(0.));
+
+        let max = r.max(g).max(b);
+        if max > 1. {
+            Rgb::from_linear(r / max, g / max, b / max)
+        } else {
+            Rgb::from_linear(r, g, b)
+        }
+    }
+
+    /// Approximates the colour of a black body at the given temperature, as used by Home
+    /// Assistant's `color_temp_kelvin`.
+    pub fn from_kelvin(kelvin: f32) -> Rgb {
+        let temp = kelvin.clamp(1000., 40000.) / 100.;
+
+        let r = if temp <= 66. {
+            255.
+        } else {
+            329.69873 * (temp - 60.).powf(-0.13320476)
+        };
+        let g = if temp <= 66. {
+            99.4708 * temp.ln() - 161.11957
+        } else {
+            288.12216 * (temp - 60.).powf(-0.075514846)
+        };
+        let b = if temp >= 66. {
+            255.
+        } else if temp <= 19. {
+            0.
+        } else {
+            138.51773 * (temp - 10.).ln() - 305.0448
+        };
+
+        Rgb::from_linear(r / 255., g / 255., b / 255.)
+    }
+
+    fn from_linear(r: f32, g: f32, b: f32) -> Rgb {
+        let channel = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+
+        Rgb {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        }
+    }
+}
+
+impl From<Rgb> for [u8; 3] {
+    fn from(rgb: Rgb) -> Self {
+        [rgb.r, rgb.g, rgb.b]
+    }
+}
+
+impl From<[u8; 3]> for Rgb {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Rgb { r, g, b }
+    }
+}
+
+impl Mul<f32> for Rgb {
+    type Output = Rgb;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Rgb {
+            r: (self.r as f32 * rhs) as u8,
+            g: (self.g as f32 * rhs) as u8,
+            b: (self.b as f32 * rhs) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether every channel of `a` is within `tolerance` of `b`.
+    fn close(a: Rgb, b: Rgb, tolerance: u8) -> bool {
+        a.r.abs_diff(b.r) <= tolerance
+            && a.g.abs_diff(b.g) <= tolerance
+            && a.b.abs_diff(b.b) <= tolerance
+    }
+
+    #[test]
+    fn converts_hue_and_saturation() {
+        assert_eq!(Rgb::from_hs(0., 100.), Rgb::from([255, 0, 0]));
+        assert_eq!(Rgb::from_hs(120., 100.), Rgb::from([0, 255, 0]));
+        assert_eq!(Rgb::from_hs(240., 100.), Rgb::from([0, 0, 255]));
+        assert_eq!(Rgb::from_hs(360., 100.), Rgb::from([255, 0, 0]));
+        assert_eq!(Rgb::from_hs(60., 50.), Rgb::from([255, 255, 128]));
+        assert_eq!(Rgb::from_hs(200., 0.), Rgb::WHITE);
+    }
+
+    #[test]
+    fn converts_chromaticity() {
+        // D65 white point, slightly off white as the wide gamut primaries don't quite match sRGB
+        let white = Rgb::from_xy(0.3127, 0.329);
+        assert!(close(white, Rgb::WHITE, 12), "{white:?}");
+
+        let red = Rgb::from_xy(0.7, 0.299);
+        assert_eq!(red.r, 255);
+        assert!(red.g < 40 && red.b < 40, "{red:?}");
+
+        assert_eq!(Rgb::from_xy(0.3, 0.), Rgb::BLACK);
+    }
+
+    #[test]
+    fn converts_colour_temperature() {
+        let daylight = Rgb::from_kelvin(6500.);
+        assert!(close(daylight, Rgb::WHITE, 6), "{daylight:?}");
+
+        let candle = Rgb::from_kelvin(2000.);
+        assert_eq!(candle.r, 255);
+        assert!(candle.g < 160 && candle.b < 40, "{candle:?}");
+
+        let sky = Rgb::from_kelvin(15000.);
+        assert_eq!(sky.b, 255);
+        assert!(sky.r < 200, "{sky:?}");
+    }
+}
@@ -0,0 +1,296 @@
+//! Stacking effect layers into a frame, and crossfading when the effect or colour changes.
+
+use std::{mem, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    color::Rgb,
+    effect::{self, Effect},
+    layout::Layout,
+    GlobalState, LayerConfig,
+};
+
+/// How a layer is combined with what's below it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    /// Covers what's below.
+    #[default]
+    Normal,
+    Add,
+    Multiply,
+    /// Inverse of multiplying the inverses, brightening without clipping as quickly as adding.
+    Screen,
+    /// The brightest of both, per channel.
+    Max,
+}
+
+impl BlendMode {
+    /// Blends `above` onto `below`, mixed with `below` by `opacity` from 0 to 1.
+    pub fn blend(self, below: Rgb, above: Rgb, opacity: f32) -> Rgb {
+        let channel = |below: u8, above: u8| {
+            let (a, b) = (below as f32 / 255., above as f32 / 255.);
+
+            let blended = match self {
+                BlendMode::Normal => b,
+                BlendMode::Add => (a + b).min(1.),
+                BlendMode::Multiply => a * b,
+                BlendMode::Screen => 1. - (1. - a) * (1. - b),
+                BlendMode::Max => a.max(b),
+            };
+
+            ((a + (blended - a) * opacity.clamp(0., 1.)) * 255.).round() as u8
+        };
+
+        Rgb {
+            r: channel(below.r, above.r),
+            g: channel(below.g, above.g),
+            b: channel(below.b, above.b),
+        }
+    }
+}
+
+struct Layer {
+    effect: Box<dyn Effect>,
+    opacity: f32,
+    mode: BlendMode,
+    frame: Vec<Rgb>,
+}
+
+/// What's being faded away from.
+enum FadeFrom {
+    /// The previous effect, still animating.
+    Effect(Box<dyn Effect>),
+    /// A snapshot of the base layer, when a change interrupts a fade or only the colour changed.
+    Frame(Vec<Rgb>),
+}
+
+struct Fade {
+    from: FadeFrom,
+    frame: Vec<Rgb>,
+    /// Seconds since the fade started.
+    elapsed: f32,
+}
+
+/// Renders the active effect as the base layer, with the configured layers on top.
+pub struct Compositor {
+    base: Box<dyn Effect>,
+    base_name: String,
+    /// Last frame of the base layer, crossfade included.
+    base_frame: Vec<Rgb>,
+    layers: Vec<Layer>,
+    layout: Arc<Layout>,
+    /// Seconds a crossfade takes.
+    transition: f32,
+    fade: Option<Fade>,
+    /// Colours followed in the last frame, to notice them changing.
+    colors: Vec<Rgb>,
+}
+
+impl Compositor {
+    pub fn new(
+        effect: &str,
+        layers: &[LayerConfig],
+        transition: f32,
+        layout: Arc<Layout>,
+    ) -> anyhow::Result<Self> {
+        let from_name = |name: &str| {
+            effect::from_name(name, &layout).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown effect {name:?}, expected one of {:?}",
+                    effect::names()
+                )
+            })
+        };
+
+        let layers = layers
+            .iter()
+            .map(|layer| {
+                Ok(Layer {
+                    effect: from_name(&layer.effect)?,
+                    opacity: layer.opacity,
+                    mode: layer.blend,
+                    frame: Vec::new(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Compositor {
+            base: from_name(effect)?,
+            base_name: effect.to_string(),
+            base_frame: Vec::new(),
+            layers,
+            layout,
+            transition,
+            fade: None,
+            colors: Vec::new(),
+        })
+    }
+
+    /// Advances every layer by `dt` seconds and writes the composed frame into `leds`.
+    pub fn render(&mut self, dt: f32, global: &GlobalState, leds: &mut [Rgb]) {
+        if global.effect != self.base_name {
+            if let Some(new) = effect::from_name(&global.effect, &self.layout) {
+                let old = mem::replace(&mut self.base, new);
+                self.start_fade(FadeFrom::Effect(old));
+            }
+            self.base_name = global.effect.clone();
+        }
+
+        let colors = global.colors();
+        if colors != self.colors {
+            // Nothing to fade from on the first frame
+            if !self.colors.is_empty() {
+                self.start_fade(FadeFrom::Frame(self.base_frame.clone()));
+            }
+            self.colors = colors;
+        }
+
+        self.base.render(dt, global, leds);
+
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += dt;
+
+            fade.frame.resize(leds.len(), Rgb::BLACK);
+            match &mut fade.from {
+                FadeFrom::Effect(effect) => effect.render(dt, global, &mut fade.frame),
+                FadeFrom::Frame(frame) => {
+                    frame.resize(leds.len(), Rgb::BLACK);
+                    fade.frame.copy_from_slice(frame);
+                }
+            }
+
+            let progress = fade.elapsed / self.transition;
+            for (led, &from) in leds.iter_mut().zip(&fade.frame) {
+                *led = BlendMode::Normal.blend(from, *led, progress);
+            }
+
+            if progress >= 1. {
+                self.fade = None;
+            }
+        }
+
+        self.base_frame.clear();
+        self.base_frame.extend_from_slice(leds);
+
+        for layer in &mut self.layers {
+            layer.frame.resize(leds.len(), Rgb::BLACK);
+            layer.effect.render(dt, global, &mut layer.frame);
+
+            for (led, &above) in leds.iter_mut().zip(&layer.frame) {
+                *led = layer.mode.blend(*led, above, layer.opacity);
+            }
+        }
+    }
+
+    fn start_fade(&mut self, from: FadeFrom) {
+        if self.transition <= 0. {
+            return;
+        }
+
+        // Continue from wherever an interrupted fade got to
+        let from = match self.fade {
+            Some(_) => FadeFrom::Frame(self.base_frame.clone()),
+            None => from,
+        };
+
+        self.fade = Some(Fade {
+            from,
+            frame: Vec::new(),
+            elapsed: 0.,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LayoutConfig;
+
+    const RED: Rgb = Rgb { r: 200, g: 0, b: 0 };
+    const BLUE: Rgb = Rgb { r: 0, g: 0, b: 200 };
+
+    fn compositor(effect: &str, layers: &[LayerConfig]) -> Compositor {
+        let layout = Layout::new(&LayoutConfig::Linear, 2).unwrap();
+        Compositor::new(effect, layers, 1., Arc::new(layout)).unwrap()
+    }
+
+    #[test]
+    fn blends() {
+        let below = Rgb::grey(128);
+        let above = Rgb::grey(255);
+
+        assert_eq!(BlendMode::Normal.blend(below, above, 1.), above);
+        assert_eq!(BlendMode::Normal.blend(below, above, 0.), below);
+        assert_eq!(
+            BlendMode::Normal.blend(Rgb::BLACK, above, 0.5),
+            Rgb::grey(128)
+        );
+        assert_eq!(BlendMode::Add.blend(below, below, 1.), Rgb::WHITE);
+        assert_eq!(BlendMode::Multiply.blend(below, above, 1.), below);
+        assert_eq!(BlendMode::Screen.blend(below, Rgb::BLACK, 1.), below);
+        assert_eq!(
+            BlendMode::Max.blend(RED, BLUE, 1.),
+            Rgb {
+                r: 200,
+                g: 0,
+                b: 200
+            }
+        );
+    }
+
+    #[test]
+    fn crossfades_colour_changes() {
+        let mut compositor = compositor("solid", &[]);
+        let mut global = GlobalState::new("solid", &[]);
+        let mut leds = [Rgb::BLACK; 2];
+
+        global.priorities.set("test", 100, RED, None);
+        compositor.render(0.1, &global, &mut leds);
+        assert_eq!(leds, [RED; 2]);
+
+        global.priorities.set("test", 100, BLUE, None);
+        compositor.render(0.5, &global, &mut leds);
+        assert_eq!(
+            leds,
+            [Rgb {
+                r: 100,
+                g: 0,
+                b: 100
+            }; 2]
+        );
+
+        compositor.render(0.5, &global, &mut leds);
+        assert_eq!(leds, [BLUE; 2]);
+        assert!(compositor.fade.is_none());
+    }
+
+    #[test]
+    fn composes_layers() {
+        let layers = [LayerConfig {
+            effect: "solid".to_string(),
+            opacity: 0.5,
+            blend: BlendMode::Normal,
+        }];
+        let mut compositor = compositor("stars", &layers);
+        let mut global = GlobalState::new("stars", &[]);
+        global.priorities.set("test", 100, RED, None);
+
+        let mut leds = [Rgb::BLACK; 2];
+        compositor.render(0., &global, &mut leds);
+        assert_eq!(leds, [Rgb { r: 100, g: 0, b: 0 }; 2]);
+
+        // Switching effects fades the base layer, starting from the old effect
+        global.effect = "solid".to_string();
+        compositor.render(0., &global, &mut leds);
+        assert_eq!(leds, [Rgb { r: 100, g: 0, b: 0 }; 2]);
+        assert!(matches!(
+            compositor.fade,
+            Some(Fade {
+                from: FadeFrom::Effect(_),
+                ..
+            })
+        ));
+    }
+}
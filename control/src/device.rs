@@ -0,0 +1,76 @@
+//! Pushing device settings to strips running our firmware.
+
+use std::{
+    net::{IpAddr, UdpSocket},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use home_leds_protocol::settings::{
+    self, ColorOrder, LocalEffect, Message, Settings, MAX_MESSAGE_LEN,
+};
+
+use crate::DeviceConfig;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Updates the settings of the device at `address` to match the config, if they differ.
+pub fn configure(address: IpAddr, leds: usize, config: &DeviceConfig) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect((address, settings::PORT))?;
+
+    let current = request(&socket, Message::Get)?;
+
+    let color_order = match &config.color_order {
+        Some(name) => {
+            ColorOrder::from_name(name).ok_or_else(|| anyhow!("Unknown colour order {name:?}"))?
+        }
+        None => current.color_order,
+    };
+
+    let local_effect = match &config.local_effect {
+        Some(name) => LocalEffect::from_name(name, config.local_color.unwrap_or([255; 3]))
+            .ok_or_else(|| anyhow!("Unknown local effect {name:?}"))?,
+        None => current.local_effect,
+    };
+
+    let wanted = Settings {
+        leds: leds.try_into()?,
+        color_order,
+        data_pin: config.data_pin.unwrap_or(current.data_pin),
+        start_universe: config.start_universe.or(current.start_universe),
+        start_channel: config.start_channel.unwrap_or(current.start_channel),
+        channels_per_universe: config
+            .channels_per_universe
+            .unwrap_or(current.channels_per_universe),
+        local_effect,
+        interpolate: config.interpolate.unwrap_or(current.interpolate),
+    };
+
+    if wanted != current {
+        println!("Updating device settings from {current:?} to {wanted:?}");
+        let applied = request(&socket, Message::Set(wanted))?;
+        if applied != wanted {
+            bail!("Device kept its settings {applied:?}, it may not support {wanted:?}");
+        }
+    }
+
+    Ok(())
+}
+
+fn request(socket: &UdpSocket, message: Message) -> anyhow::Result<Settings> {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+
+    let len = message
+        .encode(&mut buf)
+        .map_err(|e| anyhow!("Failed to encode settings message: {e:?}"))?;
+    socket.send(&buf[..len])?;
+
+    let n = socket.recv(&mut buf)?;
+    match Message::decode(&buf[..n]) {
+        Ok(Message::Current(settings)) => Ok(settings),
+        Ok(other) => bail!("Unexpected reply to settings message: {other:?}"),
+        Err(e) => bail!("Invalid reply to settings message: {e:?}"),
+    }
+}
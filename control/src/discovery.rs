@@ -0,0 +1,239 @@
+//! Finding strips running our firmware with mDNS, and following them when their address changes.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use home_leds_protocol::{
+    dns::TYPE_A,
+    mdns::{self, Name, RecordData, SERVICE, TXT_LEDS, TYPE_PTR, TYPE_SRV, TYPE_TXT},
+};
+use serde::Serialize;
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, timeout_at, Instant},
+};
+
+/// How long to wait for responses to a query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a followed device is looked up again, in case its address changed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A strip advertising itself on the network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Device {
+    /// Hostname, without `.local`.
+    pub name: String,
+    pub ip: IpAddr,
+    /// Port native packets are received on.
+    pub port: u16,
+    pub leds: Option<usize>,
+}
+
+/// The hostname to look up with mDNS if `address` is a `.local` name, without the suffix, along
+/// with the port if one is given.
+pub fn mdns_name(address: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port.parse().ok()?)),
+        None => (address, None),
+    };
+    let (name, suffix) = host.rsplit_once('.')?;
+
+    suffix.eq_ignore_ascii_case("local").then_some((name, port))
+}
+
+/// Looks for devices on the network, either every one of them or only the one called `name`.
+pub async fn find(name: Option<&str>) -> anyhow::Result<Vec<Device>> {
+    let instance;
+    let host;
+    let questions: Vec<(&[&str], u16)> = match name {
+        Some(name) => {
+            instance = [name, SERVICE];
+            host = [name, "local"];
+            vec![
+                (&instance[..], TYPE_SRV),
+                (&instance[..], TYPE_TXT),
+                (&host[..], TYPE_A),
+            ]
+        }
+        None => vec![(&[SERVICE][..], TYPE_PTR)],
+    };
+
+    let mut buf = [0; 1500];
+    let len = mdns::query(&questions, &mut buf)
+        .map_err(|e| anyhow!("Failed to build mDNS query: {e:?}"))?;
+
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .send_to(&buf[..len], (Ipv4Addr::from(mdns::ADDRESS), mdns::PORT))
+        .await?;
+
+    let mut responses = Responses::default();
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    while let Ok(received) = timeout_at(deadline, socket.recv(&mut buf)).await {
+        responses.add(&buf[..received?]);
+    }
+
+    let mut devices = responses.devices();
+    if let Some(name) = name {
+        devices.retain(|device| device.name.eq_ignore_ascii_case(name));
+    }
+
+    Ok(devices)
+}
+
+/// Looks up `device` every now and then, updating `address` when its IP changes.
+pub async fn follow(device: Device, address: Arc<Mutex<SocketAddr>>) {
+    let mut ip = device.ip;
+
+    loop {
+        sleep(REFRESH_INTERVAL).await;
+
+        match find(Some(&device.name)).await {
+            Ok(found) => match found.first() {
+                Some(found) if found.ip != ip => {
+                    println!("{} moved from {ip} to {}", device.name, found.ip);
+
+                    ip = found.ip;
+                    address.lock().unwrap().set_ip(ip);
+                }
+                Some(_) => {}
+                None => println!("{} didn't respond, still sending to {ip}", device.name),
+            },
+            Err(e) => println!("Failed to look up {}: {e}", device.name),
+        }
+    }
+}
+
+/// Records gathered from responses, by lowercase hostname.
+#[derive(Default)]
+struct Responses {
+    /// Port and target host of every service instance.
+    services: HashMap<String, (u16, String)>,
+    leds: HashMap<String, usize>,
+    addresses: HashMap<String, Ipv4Addr>,
+}
+
+impl Responses {
+    fn add(&mut self, message: &[u8]) {
+        let Ok(records) = mdns::records(message) else {
+            return;
+        };
+
+        for record in records.map_while(Result::ok) {
+            let Some(label) = first_label(&record.name) else {
+                continue;
+            };
+
+            match record.data {
+                RecordData::Srv { port, target } if record.name.is(&[label.as_str(), SERVICE]) => {
+                    if let Some(target) = first_label(&target) {
+                        self.services.insert(label, (port, target));
+                    }
+                }
+                RecordData::Txt(data) if record.name.is(&[label.as_str(), SERVICE]) => {
+                    let leds = mdns::txt_value(data, TXT_LEDS)
+                        .and_then(|value| std::str::from_utf8(value).ok())
+                        .and_then(|value| value.parse().ok());
+
+                    if let Some(leds) = leds {
+                        self.leds.insert(label, leds);
+                    }
+                }
+                RecordData::A(address) if record.name.is(&[label.as_str(), "local"]) => {
+                    self.addresses.insert(label, address.into());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Every service instance whose address is known, sorted by name.
+    fn devices(&self) -> Vec<Device> {
+        let mut devices = self
+            .services
+            .iter()
+            .filter_map(|(name, (port, host))| {
+                Some(Device {
+                    name: name.clone(),
+                    ip: IpAddr::V4(*self.addresses.get(host)?),
+                    port: *port,
+                    leds: self.leds.get(name).copied(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        devices
+    }
+}
+
+fn first_label(name: &Name) -> Option<String> {
+    std::str::from_utf8(name.first_label())
+        .ok()
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use home_leds_protocol::packet;
+
+    use super::*;
+
+    fn announcement(hostname: &str, address: [u8; 4], leds: u16) -> Vec<u8> {
+        let service = mdns::Service {
+            hostname,
+            address,
+            port: packet::PORT,
+            leds,
+            firmware_version: "0.1.0",
+        };
+
+        let mut buf = [0; 512];
+        let len = mdns::announce(&service, &mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn recognizes_mdns_names() {
+        assert_eq!(mdns_name("desk.local"), Some(("desk", None)));
+        assert_eq!(mdns_name("Desk.LOCAL:7777"), Some(("Desk", Some(7777))));
+        assert_eq!(mdns_name("desk.local:port"), None);
+        assert_eq!(mdns_name("desk.example.com"), None);
+        assert_eq!(mdns_name("192.168.1.50"), None);
+    }
+
+    #[test]
+    fn puts_devices_together_from_responses() {
+        let mut responses = Responses::default();
+        responses.add(&announcement("Shelf", [192, 168, 1, 51], 60));
+        responses.add(&announcement("desk", [192, 168, 1, 50], 100));
+        // Moved since
+        responses.add(&announcement("desk", [192, 168, 1, 52], 100));
+        responses.add(b"not mdns");
+
+        assert_eq!(
+            responses.devices(),
+            vec![
+                Device {
+                    name: "desk".to_string(),
+                    ip: [192, 168, 1, 52].into(),
+                    port: packet::PORT,
+                    leds: Some(100),
+                },
+                Device {
+                    name: "shelf".to_string(),
+                    ip: [192, 168, 1, 51].into(),
+                    port: packet::PORT,
+                    leds: Some(60),
+                },
+            ]
+        );
+    }
+}
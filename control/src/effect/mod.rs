@@ -0,0 +1,98 @@
+mod solid;
+mod stars;
+mod wave;
+
+use std::sync::Arc;
+
+use crate::{
+    layout::{Layout, Point},
+    GlobalState, Rgb,
+};
+
+/// A number an effect can be tuned with at runtime, see [`GlobalState::param`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Param {
+    pub name: &'static str,
+    pub default: f32,
+}
+
+/// An effect that can be selected by name, in the config file, over MQTT or through the API.
+pub struct Kind {
+    pub name: &'static str,
+    pub params: &'static [Param],
+    create: fn(&Arc<Layout>) -> Box<dyn Effect>,
+}
+
+/// Every effect, with the name its parameters are stored under in [`GlobalState::params`].
+const KINDS: &[Kind] = &[
+    Kind {
+        name: stars::NAME,
+        params: stars::PARAMS,
+        create: |_| Box::<stars::Stars>::default(),
+    },
+    Kind {
+        name: solid::NAME,
+        params: &[],
+        create: |_| Box::new(solid::Solid),
+    },
+    Kind {
+        name: wave::NAME,
+        params: wave::PARAMS,
+        create: |layout| {
+            Box::new(Mapped {
+                effect: wave::Wave::default(),
+                layout: layout.clone(),
+            })
+        },
+    },
+];
+
+/// Names of all effects.
+pub fn names() -> Vec<&'static str> {
+    KINDS.iter().map(|kind| kind.name).collect()
+}
+
+pub fn find(name: &str) -> Option<&'static Kind> {
+    KINDS.iter().find(|kind| kind.name == name)
+}
+
+/// The parameters of the effect with the given name.
+pub fn params(name: &str) -> &'static [Param] {
+    find(name).map_or(&[], |kind| kind.params)
+}
+
+/// An animation that renders a full frame of LED colours at a time.
+pub trait Effect: Send {
+    /// Advances the animation by `dt` seconds and writes the new frame into `leds`.
+    fn render(&mut self, dt: f32, global: &GlobalState, leds: &mut [Rgb]);
+}
+
+/// An animation defined by the colour at every point of the layout, rather than along the strip.
+pub trait Spatial: Send {
+    /// Advances the animation by `dt` seconds.
+    fn tick(&mut self, dt: f32, global: &GlobalState);
+
+    /// The colour of the LED at `index`, which is at `point` in the layout.
+    fn color_at(&self, point: Point, index: usize, global: &GlobalState) -> Rgb;
+}
+
+/// Renders a spatial effect to the strip through the layout.
+struct Mapped<S> {
+    effect: S,
+    layout: Arc<Layout>,
+}
+
+impl<S: Spatial> Effect for Mapped<S> {
+    fn render(&mut self, dt: f32, global: &GlobalState, leds: &mut [Rgb]) {
+        self.effect.tick(dt, global);
+
+        for (index, (led, &point)) in leds.iter_mut().zip(self.layout.points()).enumerate() {
+            *led = self.effect.color_at(point, index, global);
+        }
+    }
+}
+
+/// Creates the effect with the given name, as listed in [`names`].
+pub fn from_name(name: &str, layout: &Arc<Layout>) -> Option<Box<dyn Effect>> {
+    find(name).map(|kind| (kind.create)(layout))
+}
@@ -0,0 +1,15 @@
+use super::Effect;
+use crate::{GlobalState, Rgb};
+
+pub const NAME: &str = "solid";
+
+/// Lights every LED in the colour it is currently following.
+pub struct Solid;
+
+impl Effect for Solid {
+    fn render(&mut self, _dt: f32, global: &GlobalState, leds: &mut [Rgb]) {
+        for (index, led) in leds.iter_mut().enumerate() {
+            *led = global.color_at(index);
+        }
+    }
+}
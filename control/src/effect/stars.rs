@@ -0,0 +1,103 @@
+use super::{Effect, Param};
+use crate::{GlobalState, Rgb};
+
+pub const NAME: &str = "stars";
+
+/// Chance of a star appearing at an idle LED, per second.
+const RATE: Param = Param {
+    name: "rate",
+    default: 0.1,
+};
+
+pub const PARAMS: &[Param] = &[RATE];
+
+/// Randomly twinkling stars in the current colour.
+#[derive(Default)]
+pub struct Stars {
+    state: Vec<LedState>,
+}
+
+impl Effect for Stars {
+    fn render(&mut self, dt: f32, global: &GlobalState, leds: &mut [Rgb]) {
+        self.state.resize(leds.len(), LedState::Idle);
+        let rate = global.param(NAME, RATE);
+
+        for (index, (state, led)) in self.state.iter_mut().zip(leds.iter_mut()).enumerate() {
+            state.tick(dt, rate, global.color_at(index));
+            *led = Rgb::from(*state);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LedState {
+    Idle,
+    StarFadeIn {
+        color: Rgb,
+        progress: f32,
+        speed: f32,
+    },
+    StarFadeOut {
+        color: Rgb,
+        progress: f32,
+        speed: f32,
+    },
+}
+
+impl LedState {
+    fn tick(&mut self, dt: f32, rate: f32, color: Rgb) {
+        match *self {
+            LedState::Idle => {
+                if rand::random::<f32>() < dt * rate {
+                    *self = LedState::StarFadeIn {
+                        color,
+                        progress: 0.,
+                        speed: rand::random::<f32>() * 0.5 + 0.5,
+                    };
+                }
+            }
+            LedState::StarFadeIn {
+                color,
+                ref mut progress,
+                speed,
+            } => {
+                *progress += dt * speed;
+                if *progress >= 1. {
+                    *self = LedState::StarFadeOut {
+                        color,
+                        progress: 1.,
+                        speed: rand::random::<f32>() * 0.9 + 0.1,
+                    };
+                }
+            }
+            LedState::StarFadeOut {
+                color: _,
+                ref mut progress,
+                speed,
+            } => {
+                *progress -= dt * speed;
+                if *progress <= 0.0 {
+                    *self = LedState::Idle;
+                }
+            }
+        }
+    }
+}
+
+impl From<LedState> for Rgb {
+    fn from(state: LedState) -> Self {
+        match state {
+            LedState::Idle => Rgb::BLACK,
+            LedState::StarFadeIn {
+                color,
+                progress,
+                speed: _,
+            } => color * progress,
+            LedState::StarFadeOut {
+                color,
+                progress,
+                speed: _,
+            } => color * progress,
+        }
+    }
+}
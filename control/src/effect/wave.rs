@@ -0,0 +1,39 @@
+use std::f32::consts::TAU;
+
+use super::{Param, Spatial};
+use crate::{layout::Point, GlobalState, Rgb};
+
+pub const NAME: &str = "wave";
+
+/// Bands across the longest side of the layout.
+const BANDS: Param = Param {
+    name: "bands",
+    default: 2.,
+};
+
+/// Bands passing any point per second.
+const SPEED: Param = Param {
+    name: "speed",
+    default: 0.5,
+};
+
+pub const PARAMS: &[Param] = &[BANDS, SPEED];
+
+/// Bands of the current colour sweeping diagonally across the layout.
+#[derive(Default)]
+pub struct Wave {
+    phase: f32,
+}
+
+impl Spatial for Wave {
+    fn tick(&mut self, dt: f32, global: &GlobalState) {
+        self.phase = (self.phase + dt * global.param(NAME, SPEED)).rem_euclid(1.);
+    }
+
+    fn color_at(&self, point: Point, index: usize, global: &GlobalState) -> Rgb {
+        let position = (point.x + point.y + point.z) * global.param(NAME, BANDS) - self.phase;
+        let brightness = 0.5 + 0.5 * (position * TAU).sin();
+
+        global.color_at(index) * brightness
+    }
+}
@@ -0,0 +1,130 @@
+mod rest;
+mod websocket;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{backoff::Backoff, ConnectionState, GlobalState, HomeAssistantConfig, Rgb};
+
+/// How long to fall back to REST polling before trying the websocket again.
+const FALLBACK_DURATION: Duration = Duration::from_secs(60);
+
+/// Name of the colour input set from `entity`, see [`crate::priority`].
+const SOURCE: &str = "home_assistant";
+
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Deserialize)]
+struct LightAttributes {
+    color_mode: Option<String>,
+    brightness: Option<u8>,
+    rgb_color: Option<[u8; 3]>,
+    hs_color: Option<[f32; 2]>,
+    xy_color: Option<[f32; 2]>,
+    color_temp_kelvin: Option<f32>,
+}
+
+impl LightAttributes {
+    /// The colour of the light at full brightness, preferring whatever its colour mode says it
+    /// was set with.
+    fn full_color(&self) -> Rgb {
+        let rgb = self.rgb_color.map(Rgb::from);
+        let hs = self.hs_color.map(|[h, s]| Rgb::from_hs(h, s));
+        let xy = self.xy_color.map(|[x, y]| Rgb::from_xy(x, y));
+        let temp = self.color_temp_kelvin.map(Rgb::from_kelvin);
+
+        let preferred = match self.color_mode.as_deref() {
+            Some("rgb" | "rgbw" | "rgbww") => rgb,
+            Some("hs") => hs,
+            Some("xy") => xy,
+            Some("color_temp") => temp,
+            Some("onoff" | "brightness" | "white") => Some(Rgb::WHITE),
+            _ => None,
+        };
+
+        preferred
+            .or(rgb)
+            .or(hs)
+            .or(xy)
+            .or(temp)
+            .unwrap_or(Rgb::WHITE)
+    }
+
+    /// The colour the strip should show for the light, or black if it is off.
+    fn color(&self, on: bool) -> Rgb {
+        if !on {
+            return Rgb::BLACK;
+        }
+
+        let brightness = self.brightness.unwrap_or(255);
+        self.full_color() * (brightness as f32 / 255.)
+    }
+}
+
+/// Follows the configured lights, preferring the websocket API and polling the REST API while
+/// the websocket is unavailable. Retries with increasing delays while neither works.
+pub async fn run(config: HomeAssistantConfig, global_state: Arc<Mutex<GlobalState>>) {
+    let mut backoff = Backoff::new(MIN_RETRY_DELAY, MAX_RETRY_DELAY);
+
+    loop {
+        if let Err(e) = websocket::run(&config, &global_state).await {
+            println!("Home Assistant websocket failed, falling back to polling: {e}");
+        }
+
+        match rest::poll(&config, &global_state, FALLBACK_DURATION).await {
+            Ok(()) => continue,
+            Err(e) => println!("Polling Home Assistant failed: {e}"),
+        }
+
+        let previous = set_connection(&global_state, ConnectionState::Disconnected);
+        if previous == ConnectionState::Connected {
+            backoff.reset();
+        }
+
+        let delay = backoff.next_delay();
+        println!("Reconnecting to Home Assistant in {delay:?}");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Updates the connection state, returning the previous one.
+fn set_connection(global_state: &Mutex<GlobalState>, state: ConnectionState) -> ConnectionState {
+    std::mem::replace(&mut global_state.lock().unwrap().connection, state)
+}
+
+/// Applies a new colour of `entity` to every part of the strip that follows it.
+fn update_light(
+    config: &HomeAssistantConfig,
+    global_state: &Mutex<GlobalState>,
+    entity: &str,
+    color: Rgb,
+) {
+    let mut global_state = global_state.lock().unwrap();
+
+    if config.entity == entity {
+        global_state
+            .priorities
+            .set(SOURCE, config.priority, color, None);
+    }
+
+    for (index, segment) in config.segments.iter().enumerate() {
+        if segment.entity == entity {
+            global_state.segments[index].color = color;
+        }
+    }
+}
+
+/// All entities that are followed by some part of the strip.
+fn entities(config: &HomeAssistantConfig) -> impl Iterator<Item = &str> {
+    std::iter::once(config.entity.as_str()).chain(
+        config
+            .segments
+            .iter()
+            .map(|segment| segment.entity.as_str()),
+    )
+}
@@ -0,0 +1,46 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use home_assistant_rest::{get::StateEnum, Client};
+use serde::{de::value::MapDeserializer, Deserialize};
+
+use super::LightAttributes;
+use crate::{ConnectionState, GlobalState, HomeAssistantConfig};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Polls the followed lights through the REST API for `duration`.
+pub async fn poll(
+    config: &HomeAssistantConfig,
+    global_state: &Mutex<GlobalState>,
+    duration: Duration,
+) -> anyhow::Result<()> {
+    let client = Client::new(&config.url, &config.token)?;
+    let start = Instant::now();
+
+    while start.elapsed() < duration {
+        let api_status = client.get_api_status().await?;
+
+        if api_status.message != "API running." {
+            anyhow::bail!("API is NOT running");
+        }
+
+        super::set_connection(global_state, ConnectionState::Connected);
+
+        for entity in super::entities(config) {
+            let state_entity = client.get_states_of_entity(entity).await?;
+            let light = LightAttributes::deserialize(MapDeserializer::new(
+                state_entity.attributes.into_iter(),
+            ))?;
+            let on = matches!(state_entity.state, Some(StateEnum::String(state)) if state == "on");
+
+            super::update_light(config, global_state, entity, light.color(on));
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    Ok(())
+}
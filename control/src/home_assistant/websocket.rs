@@ -0,0 +1,313 @@
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use super::LightAttributes;
+use crate::{ConnectionState, GlobalState, HomeAssistantConfig};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const SUBSCRIBE_ID: u64 = 1;
+const GET_STATES_ID: u64 = 2;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    AuthRequired,
+    AuthOk,
+    AuthInvalid {
+        message: String,
+    },
+    Result {
+        id: u64,
+        success: bool,
+        #[serde(default)]
+        result: Value,
+        #[serde(default)]
+        error: Value,
+    },
+    Event {
+        event: Event,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    data: StateChanged,
+}
+
+#[derive(Deserialize)]
+struct StateChanged {
+    new_state: Option<EntityState>,
+}
+
+#[derive(Deserialize)]
+struct EntityState {
+    entity_id: String,
+    state: String,
+    attributes: Value,
+}
+
+/// Follows the configured lights through Home Assistant's websocket API until the connection
+/// fails.
+pub async fn run(
+    config: &HomeAssistantConfig,
+    global_state: &Mutex<GlobalState>,
+) -> anyhow::Result<()> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(websocket_url(&config.url)).await?;
+
+    let ServerMessage::AuthRequired = receive(&mut socket).await? else {
+        bail!("Expected auth_required message");
+    };
+
+    send(
+        &mut socket,
+        json!({ "type": "auth", "access_token": config.token }),
+    )
+    .await?;
+
+    match receive(&mut socket).await? {
+        ServerMessage::AuthOk => {
+            super::set_connection(global_state, ConnectionState::Connected);
+        }
+        ServerMessage::AuthInvalid { message } => bail!("Authentication failed: {message}"),
+        _ => bail!("Expected auth_ok message"),
+    }
+
+    send(
+        &mut socket,
+        json!({ "id": SUBSCRIBE_ID, "type": "subscribe_events", "event_type": "state_changed" }),
+    )
+    .await?;
+    send(
+        &mut socket,
+        json!({ "id": GET_STATES_ID, "type": "get_states" }),
+    )
+    .await?;
+
+    loop {
+        match receive(&mut socket).await? {
+            ServerMessage::Result {
+                id,
+                success: false,
+                error,
+                ..
+            } => bail!("Request {id} failed: {error}"),
+            ServerMessage::Result {
+                id: GET_STATES_ID,
+                result,
+                ..
+            } => {
+                for state in Vec::<EntityState>::deserialize(result)? {
+                    update_light(config, global_state, &state)?;
+                }
+            }
+            ServerMessage::Event { event } => {
+                if let Some(state) = event.data.new_state {
+                    update_light(config, global_state, &state)?;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn update_light(
+    config: &HomeAssistantConfig,
+    global_state: &Mutex<GlobalState>,
+    state: &EntityState,
+) -> anyhow::Result<()> {
+    if super::entities(config).any(|entity| entity == state.entity_id) {
+        let light = LightAttributes::deserialize(&state.attributes)?;
+        let color = light.color(state.state == "on");
+        super::update_light(config, global_state, &state.entity_id, color);
+    }
+
+    Ok(())
+}
+
+/// Turns the configured base URL (e.g. `http://homeassistant.local:8123`) into the URL of the
+/// websocket API.
+fn websocket_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{rest}"),
+        Some(("http", rest)) => format!("ws://{rest}"),
+        _ => url.to_string(),
+    };
+
+    format!("{url}/api/websocket")
+}
+
+async fn send(socket: &mut Socket, message: Value) -> anyhow::Result<()> {
+    socket.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+async fn receive(socket: &mut Socket) -> anyhow::Result<ServerMessage> {
+    loop {
+        match socket
+            .next()
+            .await
+            .ok_or_else(|| anyhow!("Connection closed"))??
+        {
+            Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+            Message::Close(_) => bail!("Connection closed"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+    use crate::{Rgb, SegmentConfig};
+
+    type ServerSocket = WebSocketStream<TcpStream>;
+
+    async fn server_send(socket: &mut ServerSocket, message: Value) {
+        socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    async fn server_receive(socket: &mut ServerSocket) -> Value {
+        let message = socket.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    async fn mock_server(token: &str) -> (HomeAssistantConfig, TcpListener) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = HomeAssistantConfig {
+            url: format!("http://{}/", listener.local_addr().unwrap()),
+            token: token.to_string(),
+            entity: "light.desk".to_string(),
+            disconnected_color: None,
+            priority: 100,
+            segments: vec![SegmentConfig {
+                entity: "light.shelf".to_string(),
+                start: 0,
+                leds: 10,
+            }],
+        };
+
+        (config, listener)
+    }
+
+    #[test]
+    fn builds_websocket_url() {
+        assert_eq!(
+            websocket_url("http://ha.local:8123"),
+            "ws://ha.local:8123/api/websocket"
+        );
+        assert_eq!(
+            websocket_url("https://ha.example.com/"),
+            "wss://ha.example.com/api/websocket"
+        );
+    }
+
+    #[tokio::test]
+    async fn follows_state_changes() {
+        let (config, listener) = mock_server("secret").await;
+        let global_state = Mutex::new(GlobalState::new("solid", &config.segments));
+
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+
+            server_send(&mut socket, json!({ "type": "auth_required" })).await;
+            let auth = server_receive(&mut socket).await;
+            assert_eq!(auth, json!({ "type": "auth", "access_token": "secret" }));
+            server_send(&mut socket, json!({ "type": "auth_ok" })).await;
+
+            let subscribe = server_receive(&mut socket).await;
+            assert_eq!(subscribe["type"], "subscribe_events");
+            assert_eq!(subscribe["event_type"], "state_changed");
+            let get_states = server_receive(&mut socket).await;
+            assert_eq!(get_states["type"], "get_states");
+
+            server_send(
+                &mut socket,
+                json!({ "id": subscribe["id"], "type": "result", "success": true, "result": null }),
+            )
+            .await;
+            server_send(
+                &mut socket,
+                json!({
+                    "id": get_states["id"],
+                    "type": "result",
+                    "success": true,
+                    "result": [
+                        { "entity_id": "sun.sun", "state": "above_horizon", "attributes": {} },
+                        { "entity_id": "light.desk", "state": "on", "attributes": { "rgb_color": [255, 0, 0] } },
+                        { "entity_id": "light.shelf", "state": "on", "attributes": { "rgb_color": [0, 255, 0] } },
+                    ],
+                }),
+            )
+            .await;
+            server_send(
+                &mut socket,
+                json!({
+                    "id": subscribe["id"],
+                    "type": "event",
+                    "event": {
+                        "event_type": "state_changed",
+                        "data": {
+                            "entity_id": "light.desk",
+                            "old_state": null,
+                            "new_state": { "entity_id": "light.desk", "state": "on", "attributes": { "rgb_color": [0, 0, 255] } },
+                        },
+                    },
+                }),
+            )
+            .await;
+
+            socket.close(None).await.unwrap();
+        };
+
+        let (result, ()) = tokio::join!(run(&config, &global_state), server);
+
+        assert!(result.is_err());
+        let global_state = global_state.lock().unwrap();
+        assert_eq!(global_state.connection, ConnectionState::Connected);
+        assert_eq!(global_state.color(), Rgb::from([0, 0, 255]));
+        assert_eq!(global_state.segments[0].color, Rgb::from([0, 255, 0]));
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_token() {
+        let (config, listener) = mock_server("wrong").await;
+        let global_state = Mutex::new(GlobalState::new("solid", &config.segments));
+
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+
+            server_send(&mut socket, json!({ "type": "auth_required" })).await;
+            server_receive(&mut socket).await;
+            server_send(
+                &mut socket,
+                json!({ "type": "auth_invalid", "message": "Invalid access token" }),
+            )
+            .await;
+        };
+
+        let (result, ()) = tokio::join!(run(&config, &global_state), server);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Authentication failed: Invalid access token"
+        );
+    }
+}
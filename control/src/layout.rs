@@ -0,0 +1,276 @@
+//! Where LEDs physically are, so effects can be written in terms of space rather than strip order.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+/// A position in the layout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LayoutConfig {
+    /// One LED after another along x.
+    #[default]
+    Linear,
+    /// Rows of `width` LEDs, stacked along y.
+    Matrix {
+        width: usize,
+        /// Whether every other row runs back the other way, as when a strip is zigzagged.
+        #[serde(default)]
+        serpentine: bool,
+    },
+    /// The position of every LED in strip order, from a CSV file of `x,y[,z]` lines or a JSON
+    /// array of `[x, y(, z)]` arrays.
+    File { path: PathBuf },
+}
+
+/// The position of every LED on the canvas, in strip order.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    points: Vec<Point>,
+}
+
+impl Layout {
+    pub fn new(config: &LayoutConfig, leds: usize) -> anyhow::Result<Self> {
+        let points = match *config {
+            LayoutConfig::Linear => (0..leds)
+                .map(|index| Point {
+                    x: index as f32,
+                    ..Point::default()
+                })
+                .collect(),
+            LayoutConfig::Matrix { width: 0, .. } => bail!("Matrix layout needs a width"),
+            LayoutConfig::Matrix { width, serpentine } => (0..leds)
+                .map(|index| {
+                    let (column, row) = grid_position(index, width, serpentine);
+
+                    Point {
+                        x: column as f32,
+                        y: row as f32,
+                        z: 0.,
+                    }
+                })
+                .collect(),
+            LayoutConfig::File { ref path } => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Failed to read layout {}: {e}", path.display()))?;
+
+                let points = if path
+                    .extension()
+                    .is_some_and(|extension| extension == "json")
+                {
+                    parse_json(&contents)?
+                } else {
+                    parse_csv(&contents)?
+                };
+
+                if points.len() < leds {
+                    bail!(
+                        "Layout {} has {} positions for {leds} LEDs",
+                        path.display(),
+                        points.len()
+                    );
+                }
+
+                points
+            }
+        };
+
+        Ok(Layout {
+            points: normalize(points),
+        })
+    }
+
+    /// The position of every LED in strip order, scaled to fit in 0-1 along the layout's longest
+    /// side.
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+}
+
+/// Column and row of the LED at `index` in rows of `width` LEDs.
+pub fn grid_position(index: usize, width: usize, serpentine: bool) -> (usize, usize) {
+    let row = index / width;
+    let column = if serpentine && row % 2 == 1 {
+        width - 1 - index % width
+    } else {
+        index % width
+    };
+
+    (column, row)
+}
+
+fn point(values: &[f32]) -> Option<Point> {
+    match *values {
+        [x, y] => Some(Point { x, y, z: 0. }),
+        [x, y, z] => Some(Point { x, y, z }),
+        _ => None,
+    }
+}
+
+/// Parses lines of `x,y[,z]`, skipping a header line, empty lines and `#` comments.
+fn parse_csv(contents: &str) -> anyhow::Result<Vec<Point>> {
+    let mut points = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let values = line
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<Vec<f32>, _>>();
+
+        match values.ok().as_deref().and_then(point) {
+            Some(point) => points.push(point),
+            None if points.is_empty() && number == 0 => {}
+            None => bail!("Invalid position on line {}: {line:?}", number + 1),
+        }
+    }
+
+    Ok(points)
+}
+
+fn parse_json(contents: &str) -> anyhow::Result<Vec<Point>> {
+    serde_json::from_str::<Vec<Vec<f32>>>(contents)?
+        .iter()
+        .enumerate()
+        .map(|(index, values)| {
+            point(values).ok_or_else(|| anyhow!("Invalid position for LED {index}: {values:?}"))
+        })
+        .collect()
+}
+
+/// Moves `points` to start at zero and scales them so the longest side is 1, keeping their
+/// aspect ratio.
+fn normalize(mut points: Vec<Point>) -> Vec<Point> {
+    let min = |axis: fn(&Point) -> f32| points.iter().map(axis).fold(f32::INFINITY, f32::min);
+    let max = |axis: fn(&Point) -> f32| points.iter().map(axis).fold(f32::NEG_INFINITY, f32::max);
+
+    let origin = Point {
+        x: min(|point| point.x),
+        y: min(|point| point.y),
+        z: min(|point| point.z),
+    };
+    let size = (max(|point| point.x) - origin.x)
+        .max(max(|point| point.y) - origin.y)
+        .max(max(|point| point.z) - origin.z);
+
+    // A single LED, or none at all
+    let scale = if size > 0. { 1. / size } else { 0. };
+
+    for point in &mut points {
+        point.x = (point.x - origin.x) * scale;
+        point.y = (point.y - origin.y) * scale;
+        point.z = (point.z - origin.z) * scale;
+    }
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xy(points: &[Point]) -> Vec<(f32, f32)> {
+        points.iter().map(|point| (point.x, point.y)).collect()
+    }
+
+    #[test]
+    fn lays_out_lines_and_matrices() {
+        let linear = Layout::new(&LayoutConfig::Linear, 3).unwrap();
+        assert_eq!(xy(linear.points()), [(0., 0.), (0.5, 0.), (1., 0.)]);
+
+        let serpentine = LayoutConfig::Matrix {
+            width: 3,
+            serpentine: true,
+        };
+        assert_eq!(
+            xy(Layout::new(&serpentine, 6).unwrap().points()),
+            [
+                (0., 0.),
+                (0.5, 0.),
+                (1., 0.),
+                (1., 0.5),
+                (0.5, 0.5),
+                (0., 0.5)
+            ]
+        );
+
+        let matrix = LayoutConfig::Matrix {
+            width: 2,
+            serpentine: false,
+        };
+        assert_eq!(
+            xy(Layout::new(&matrix, 4).unwrap().points()),
+            [(0., 0.), (1., 0.), (0., 1.), (1., 1.)]
+        );
+
+        assert_eq!(
+            Layout::new(&LayoutConfig::Linear, 1).unwrap().points(),
+            [Point::default()]
+        );
+    }
+
+    #[test]
+    fn parses_csv() {
+        let points = parse_csv("x,y,z\n0, 0\n\n# corner\n2,4,1\n").unwrap();
+        assert_eq!(
+            points,
+            [
+                Point::default(),
+                Point {
+                    x: 2.,
+                    y: 4.,
+                    z: 1.
+                }
+            ]
+        );
+
+        assert!(parse_csv("0,0\n1\n").is_err());
+        assert!(parse_csv("0,0\n1,2,3,4\n").is_err());
+    }
+
+    #[test]
+    fn parses_json() {
+        let points = parse_json("[[0, 0], [2, 4, 1]]").unwrap();
+        assert_eq!(
+            points[1],
+            Point {
+                x: 2.,
+                y: 4.,
+                z: 1.
+            }
+        );
+
+        assert!(parse_json("[[0]]").is_err());
+        assert!(parse_json("{}").is_err());
+    }
+
+    #[test]
+    fn keeps_aspect_ratio() {
+        let points = normalize(vec![
+            Point {
+                x: 10.,
+                y: 5.,
+                z: 0.,
+            },
+            Point {
+                x: 14.,
+                y: 7.,
+                z: 0.,
+            },
+        ]);
+
+        assert_eq!(xy(&points), [(0., 0.), (1., 0.5)]);
+    }
+}
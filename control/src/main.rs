@@ -1,42 +1,346 @@
 #![allow(clippy::identity_op)]
 
+mod api;
+mod backoff;
+mod bridge;
+mod color;
+mod compositor;
+mod device;
+mod discovery;
+mod effect;
+mod home_assistant;
+mod layout;
+mod mqtt;
+mod output;
+mod preview;
+mod priority;
+
 use std::{
-    net::{ToSocketAddrs, UdpSocket},
-    ops::Mul,
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+    ops::Range,
     sync::{Arc, Mutex},
     thread::sleep,
     time::{Duration, Instant},
 };
 
 use config::builder::DefaultState;
-use home_assistant_rest::Client;
-use serde::{
-    de::{value::MapDeserializer, IntoDeserializer},
-    Deserialize,
+use serde::Deserialize;
+
+use crate::{
+    color::Rgb,
+    compositor::{BlendMode, Compositor},
+    effect::Param,
+    layout::{Layout, LayoutConfig},
+    output::Output,
+    preview::Preview,
+    priority::Priorities,
 };
-use serde_json::Value;
 
 #[derive(Clone, Deserialize)]
 struct Config {
-    address: String,
-    leds: usize,
+    /// The strip, if there's only one. See [`OutputConfig`], ignored if `outputs` is set.
+    address: Option<String>,
+    #[serde(default)]
+    protocol: output::Protocol,
+    leds: Option<usize>,
+    device: Option<DeviceConfig>,
+
+    /// Strips that each show a region of one shared canvas, so an effect can span all of them.
+    #[serde(default)]
+    outputs: Vec<OutputConfig>,
 
-    home_assistant: HomeAssistantConfig,
+    /// Where the LEDs of the canvas are, for spatial effects.
+    #[serde(default)]
+    layout: LayoutConfig,
+
+    #[serde(default = "default_effect")]
+    effect: String,
+    /// Effects stacked on top of `effect`, bottom first.
+    #[serde(default)]
+    layers: Vec<LayerConfig>,
+    /// Seconds to crossfade over when the effect or colour changes.
+    #[serde(default = "default_transition")]
+    transition: f32,
+
+    home_assistant: Option<HomeAssistantConfig>,
+    mqtt: Option<MqttConfig>,
+    bridge: Option<BridgeConfig>,
+    api: Option<ApiConfig>,
+}
+
+impl Config {
+    /// The configured outputs, or the single strip configured at the top level.
+    fn outputs(&self) -> Vec<OutputConfig> {
+        if !self.outputs.is_empty() {
+            return self.outputs.clone();
+        }
+
+        vec![OutputConfig {
+            address: self.address.clone(),
+            protocol: self.protocol,
+            leds: self.leds,
+            start: 0,
+            reverse: false,
+            device: self.device.clone(),
+        }]
+    }
+
+    /// Number of LEDs on the canvas according to the config alone, without looking for strips.
+    fn led_count(&self) -> anyhow::Result<usize> {
+        self.outputs()
+            .iter()
+            .map(|output| match output.leds {
+                Some(leds) => Ok(output.start + leds),
+                None => Err(anyhow::anyhow!("Set leds for every output to preview them")),
+            })
+            .try_fold(0, |count, end| Ok(end?.max(count)))
+    }
+}
+
+fn default_effect() -> String {
+    "stars".to_string()
+}
+
+fn default_transition() -> f32 {
+    1.
+}
+
+#[derive(Clone, Deserialize)]
+struct LayerConfig {
+    effect: String,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default)]
+    blend: BlendMode,
+}
+
+fn default_opacity() -> f32 {
+    1.
 }
 
 #[derive(Clone, Deserialize)]
 struct HomeAssistantConfig {
     url: String,
     token: String,
+    /// Light entity whose colour is followed by the whole strip.
+    entity: String,
+    /// Light entities whose colour overrides `entity` for a range of LEDs.
+    #[serde(default)]
+    segments: Vec<SegmentConfig>,
+    /// Colour in which the first LED blinks while Home Assistant can't be reached.
+    disconnected_color: Option<[u8; 3]>,
+    /// Priority of the colour of `entity` against other inputs, see [`priority`].
+    #[serde(default = "default_priority")]
+    priority: u8,
+}
+
+#[derive(Clone, Deserialize)]
+struct SegmentConfig {
+    entity: String,
+    start: usize,
+    leds: usize,
+}
+
+#[derive(Clone, Deserialize)]
+struct MqttConfig {
+    host: String,
+    #[serde(default = "default_mqtt_port")]
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+    /// Prefix Home Assistant listens on for discovery configs.
+    #[serde(default = "default_discovery_prefix")]
+    discovery_prefix: String,
+    /// Unique id of this strip, used as client id and in topic names.
+    #[serde(default = "default_node_id")]
+    node_id: String,
+    /// Name of the light as shown in Home Assistant.
+    #[serde(default = "default_light_name")]
+    name: String,
+    /// Priority of colours set through MQTT against other inputs, see [`priority`].
+    #[serde(default = "default_priority")]
+    priority: u8,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_node_id() -> String {
+    "home_leds".to_string()
+}
+
+fn default_light_name() -> String {
+    "Home LEDs".to_string()
+}
+
+fn default_priority() -> u8 {
+    priority::DEFAULT_PRIORITY
+}
+
+#[derive(Clone, Deserialize)]
+struct OutputConfig {
+    /// Host name or IP of the strip, optionally with a port. `.local` names are looked up with mDNS
+    /// and followed when the strip's IP changes. If not set, the first strip found is used.
+    address: Option<String>,
+    #[serde(default)]
+    protocol: output::Protocol,
+    /// Taken from the strip if it's found with mDNS and this isn't set.
+    leds: Option<usize>,
+    /// Canvas pixel shown on the first LED of the strip.
+    #[serde(default)]
+    start: usize,
+    /// Whether the strip runs the other way, showing its region back to front.
+    #[serde(default)]
+    reverse: bool,
+    /// Settings pushed to the strip on startup, if it runs our firmware.
+    device: Option<DeviceConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+struct DeviceConfig {
+    /// Order the strip expects colour channels in, like `"grb"`.
+    color_order: Option<String>,
+    /// GPIO the strip's data line is connected to.
+    data_pin: Option<u8>,
+    /// Universe holding the first LED when sending E1.31 or Art-Net to the device, 1 for E1.31
+    /// and 0 for Art-Net if not set.
+    start_universe: Option<u16>,
+    /// Channel (counting from zero) of the first LED in every universe.
+    start_channel: Option<u16>,
+    channels_per_universe: Option<u16>,
+    /// Effect the device shows by itself while no frames arrive: `"rainbow"`, `"solid"` or
+    /// `"off"`.
+    local_effect: Option<String>,
+    /// Colour of the `"solid"` local effect, white if not set.
+    local_color: Option<[u8; 3]>,
+    /// Whether the device fades between received frames, smoothing over dropped or late ones at
+    /// the cost of a frame of latency.
+    interpolate: Option<bool>,
+}
+
+#[derive(Clone, Deserialize)]
+struct BridgeConfig {
+    #[serde(default)]
+    protocol: bridge::Protocol,
+    /// Universe holding the first LED, 1 for E1.31 and 0 for Art-Net if not set.
+    start_universe: Option<u16>,
+    /// Channel (counting from zero) of the first LED in every universe.
+    #[serde(default)]
+    start_channel: u16,
+    #[serde(default = "default_channels_per_universe")]
+    channels_per_universe: u16,
+}
+
+fn default_channels_per_universe() -> u16 {
+    510
+}
+
+#[derive(Clone, Deserialize)]
+struct ApiConfig {
+    /// Address to serve the HTTP API on. Anyone who can reach it can control the strip.
+    #[serde(default = "default_api_listen")]
+    listen: SocketAddr,
+    /// Priority of colours set through the API against other inputs, see [`priority`].
+    #[serde(default = "default_priority")]
+    priority: u8,
+    /// Frames per second pushed to each client of the live frame stream, at most.
+    #[serde(default = "default_stream_rate")]
+    stream_rate: f32,
+}
+
+fn default_api_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7780))
+}
+
+fn default_stream_rate() -> f32 {
+    20.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
 }
 
 pub struct GlobalState {
+    /// Whether the Home Assistant task is currently receiving updates.
+    connection: ConnectionState,
+    on: bool,
+    brightness: u8,
+    effect: String,
+    /// Colours set by every input, the active one of which is followed.
+    priorities: Priorities,
+    segments: Vec<Segment>,
+    /// Parameters set at runtime, by effect and then by name. Unset ones are at their default.
+    params: HashMap<String, HashMap<String, f32>>,
+    /// Frame received from lighting software, shown instead of the effect while fresh.
+    bridge_frame: Option<bridge::Frame>,
+    /// The last frame sent to the strips, brightness included.
+    frame: Vec<Rgb>,
+}
+
+pub struct Segment {
+    range: Range<usize>,
     color: Rgb,
 }
 
-#[derive(Clone, Deserialize)]
-struct LightAttributes {
-    rgb_color: Option<[u8; 3]>,
+impl GlobalState {
+    fn new(effect: &str, segments: &[SegmentConfig]) -> Self {
+        GlobalState {
+            connection: ConnectionState::Connecting,
+            on: true,
+            brightness: 255,
+            effect: effect.to_string(),
+            priorities: Priorities::default(),
+            segments: segments
+                .iter()
+                .map(|segment| Segment {
+                    range: segment.start..segment.start + segment.leds,
+                    color: Rgb::BLACK,
+                })
+                .collect(),
+            params: HashMap::new(),
+            bridge_frame: None,
+            frame: Vec::new(),
+        }
+    }
+
+    /// The colour of the highest-priority live input, or black if there are none.
+    pub fn color(&self) -> Rgb {
+        self.priorities.color().unwrap_or(Rgb::BLACK)
+    }
+
+    /// Every colour followed: the main one, then those of the segments.
+    pub fn colors(&self) -> Vec<Rgb> {
+        std::iter::once(self.color())
+            .chain(self.segments.iter().map(|segment| segment.color))
+            .collect()
+    }
+
+    /// The colour the LED at `index` should follow, taking segments into account.
+    pub fn color_at(&self, index: usize) -> Rgb {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.range.contains(&index))
+            .map_or(self.color(), |segment| segment.color)
+    }
+
+    /// The current value of `param` of `effect`.
+    pub fn param(&self, effect: &str, param: Param) -> f32 {
+        self.params
+            .get(effect)
+            .and_then(|params| params.get(param.name))
+            .copied()
+            .unwrap_or(param.default)
+    }
 }
 
 #[tokio::main]
@@ -52,181 +356,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .try_deserialize::<Config>()?;
 
     let sock = UdpSocket::bind("0.0.0.0:0")?;
-    let addr = config.address.to_socket_addrs()?.next().unwrap();
-
-    let global_state = Arc::new(Mutex::new(GlobalState { color: Rgb::BLACK }));
-
-    tokio::spawn({
-        let config = config.clone();
-        let global_state = global_state.clone();
-        async move {
-            loop {
-                let client = Client::new(&config.home_assistant.url, &config.home_assistant.token)?;
-                let api_status = client.get_api_status().await?;
-
-                if api_status.message != "API running." {
-                    println!("API is NOT running");
-                } else {
-                    let state_entity = client.get_states_of_entity("light.south").await?;
-                    let light = LightAttributes::deserialize(MapDeserializer::new(
-                        state_entity.attributes.into_iter(),
-                    ))?;
-                    let color: Rgb = light.rgb_color.unwrap_or([0, 0, 0]).into();
-                    global_state.lock().unwrap().color = color;
-                }
-
-                tokio::time::sleep(Duration::from_secs(5)).await;
-            }
 
-            #[allow(unreachable_code)]
-            Result::<(), anyhow::Error>::Ok(())
-        }
-    });
-
-    let mut state = vec![LedState::Idle; config.leds];
-
-    let mut last = Instant::now();
-    loop {
-        let now = Instant::now();
-        let dt = (now - last).as_secs_f32();
-        last = now;
+    // Frames are drawn in the terminal instead of sent to the strips, which aren't needed at all
+    let mut preview = std::env::args()
+        .any(|arg| arg == "--preview")
+        .then(|| Preview::new(&config.layout));
 
-        {
-            let global_state = global_state.lock().unwrap();
-            for state in state.iter_mut() {
-                state.tick(dt, &global_state);
+    let mut outputs = Vec::new();
+    let led_count = match preview {
+        Some(_) => config.led_count()?,
+        None => {
+            for output in config.outputs() {
+                outputs.push(Output::connect(&output).await?);
             }
+
+            // Large enough for every output's region
+            outputs
+                .iter()
+                .map(|output| output.range.end)
+                .max()
+                .unwrap_or(0)
         }
+    };
 
-        let buf = state
-            .iter()
-            .copied()
-            .map(Rgb::from)
-            .flat_map(<[u8; 3]>::from)
-            .collect::<Vec<u8>>();
+    let layout = Arc::new(Layout::new(&config.layout, led_count)?);
 
-        sock.send_to(&buf, addr)?;
-        sleep(Duration::from_millis(15));
-    }
-}
+    let mut compositor = Compositor::new(
+        &config.effect,
+        &config.layers,
+        config.transition,
+        layout.clone(),
+    )?;
 
-#[derive(Debug, Clone, Copy)]
-pub struct Rgb {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
-}
+    let segments = config
+        .home_assistant
+        .as_ref()
+        .map_or(&[][..], |home_assistant| &home_assistant.segments);
+    let global_state = Arc::new(Mutex::new(GlobalState::new(&config.effect, segments)));
 
-impl Rgb {
-    const WHITE: Rgb = Rgb {
-        r: 255,
-        g: 255,
-        b: 255,
-    };
-    const BLACK: Rgb = Rgb { r: 0, g: 0, b: 0 };
+    if let Some(home_assistant) = config.home_assistant.clone() {
+        tokio::spawn(home_assistant::run(home_assistant, global_state.clone()));
+    }
 
-    const fn grey(value: u8) -> Rgb {
-        Rgb {
-            r: value,
-            g: value,
-            b: value,
-        }
+    if let Some(mqtt) = config.mqtt.clone() {
+        tokio::spawn(mqtt::run(mqtt, global_state.clone()));
     }
-}
 
-impl From<Rgb> for [u8; 3] {
-    fn from(rgb: Rgb) -> Self {
-        [rgb.r, rgb.g, rgb.b]
+    if let Some(bridge) = config.bridge.clone() {
+        let socket = bridge::bind(&bridge, led_count).await?;
+        tokio::spawn(bridge::run(bridge, socket, led_count, global_state.clone()));
     }
-}
 
-impl From<[u8; 3]> for Rgb {
-    fn from([r, g, b]: [u8; 3]) -> Self {
-        Rgb { r, g, b }
+    if let Some(api) = config.api.clone() {
+        let listener = api::bind(&api).await?;
+        println!("Serving the API on http://{}", api.listen);
+        tokio::spawn(api::run(api, listener, layout, global_state.clone()));
     }
-}
 
-impl Mul<f32> for Rgb {
-    type Output = Rgb;
+    let disconnected_color = config
+        .home_assistant
+        .as_ref()
+        .and_then(|home_assistant| home_assistant.disconnected_color)
+        .map(Rgb::from);
 
-    fn mul(self, rhs: f32) -> Self::Output {
-        Rgb {
-            r: (self.r as f32 * rhs) as u8,
-            g: (self.g as f32 * rhs) as u8,
-            b: (self.b as f32 * rhs) as u8,
-        }
-    }
-}
+    let mut leds = vec![Rgb::BLACK; led_count];
 
-#[derive(Debug, Clone, Copy)]
-pub enum LedState {
-    Idle,
-    StarFadeIn {
-        color: Rgb,
-        progress: f32,
-        speed: f32,
-    },
-    StarFadeOut {
-        color: Rgb,
-        progress: f32,
-        speed: f32,
-    },
-}
-
-impl LedState {
-    pub fn tick(&mut self, dt: f32, global: &GlobalState) {
-        match *self {
-            LedState::Idle => {
-                if rand::random::<f32>() < dt * 0.1 {
-                    *self = LedState::StarFadeIn {
-                        color: global.color,
-                        progress: 0.,
-                        speed: rand::random::<f32>() * 0.5 + 0.5,
-                    };
-                }
-            }
-            LedState::StarFadeIn {
-                color,
-                ref mut progress,
-                speed,
-            } => {
-                *progress += dt * speed;
-                if *progress >= 1. {
-                    *self = LedState::StarFadeOut {
-                        color,
-                        progress: 1.,
-                        speed: rand::random::<f32>() * 0.9 + 0.1,
-                    };
-                }
+    let start = Instant::now();
+    let mut last = start;
+    loop {
+        let now = Instant::now();
+        let dt = (now - last).as_secs_f32();
+        last = now;
+
+        let buf = {
+            let mut global_state = global_state.lock().unwrap();
+
+            match &global_state.bridge_frame {
+                Some(frame) if frame.is_fresh() => leds.copy_from_slice(&frame.pixels),
+                _ => compositor.render(dt, &global_state, &mut leds),
             }
-            LedState::StarFadeOut {
-                color: _,
-                ref mut progress,
-                speed,
-            } => {
-                *progress -= dt * speed;
-                if *progress <= 0.0 {
-                    *self = LedState::Idle;
-                }
+
+            if let (ConnectionState::Disconnected, Some(color), Some(led)) = (
+                global_state.connection,
+                disconnected_color,
+                leds.first_mut(),
+            ) {
+                let blink_on = (now - start).as_secs_f32().fract() < 0.5;
+                *led = if blink_on { color } else { Rgb::BLACK };
             }
+
+            let scale = if global_state.on {
+                global_state.brightness as f32 / 255.
+            } else {
+                0.
+            };
+
+            global_state.frame.clear();
+            global_state
+                .frame
+                .extend(leds.iter().map(|&led| led * scale));
+
+            global_state
+                .frame
+                .iter()
+                .copied()
+                .flat_map(<[u8; 3]>::from)
+                .collect::<Vec<u8>>()
+        };
+
+        // All at once, so strips sharing an effect stay in sync
+        for output in &mut outputs {
+            output.send(&sock, &buf);
         }
-    }
-}
 
-impl From<LedState> for Rgb {
-    fn from(state: LedState) -> Self {
-        match state {
-            LedState::Idle => Rgb::BLACK,
-            LedState::StarFadeIn {
-                color,
-                progress,
-                speed: _,
-            } => color * progress,
-            LedState::StarFadeOut {
-                color,
-                progress,
-                speed: _,
-            } => color * progress,
+        if let Some(preview) = &mut preview {
+            preview.draw(&buf)?;
         }
+
+        sleep(Duration::from_millis(15));
     }
 }
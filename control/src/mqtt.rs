@@ -0,0 +1,396 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rumqttc::{AsyncClient, ClientError, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{backoff::Backoff, effect, GlobalState, MqttConfig, Rgb};
+
+/// Name of the colour input set by commands, see [`crate::priority`].
+const SOURCE: &str = "mqtt";
+
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+const STATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A command sent by Home Assistant to a JSON schema light.
+#[derive(Deserialize)]
+struct Command {
+    state: Option<String>,
+    brightness: Option<u8>,
+    color: Option<Rgb>,
+    effect: Option<String>,
+    /// Seconds after which `color` stops counting and lower-priority inputs take over again.
+    timeout: Option<f32>,
+}
+
+/// The state of the strip as reported to Home Assistant.
+#[derive(Clone, PartialEq, Serialize)]
+struct State {
+    state: &'static str,
+    brightness: u8,
+    color_mode: &'static str,
+    color: Rgb,
+    effect: String,
+}
+
+impl State {
+    fn new(global_state: &GlobalState) -> Self {
+        State {
+            state: if global_state.on { "ON" } else { "OFF" },
+            brightness: global_state.brightness,
+            color_mode: "rgb",
+            color: global_state.color(),
+            effect: global_state.effect.clone(),
+        }
+    }
+}
+
+/// Exposes the strip as a light in Home Assistant through MQTT discovery.
+pub async fn run(config: MqttConfig, global_state: Arc<Mutex<GlobalState>>) {
+    let availability_topic = topic(&config, "availability");
+    let command_topic = topic(&config, "set");
+    let state_topic = topic(&config, "state");
+
+    let mut options = MqttOptions::new(&config.node_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        &availability_topic,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        options.set_credentials(username, password);
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+    let mut backoff = Backoff::new(MIN_RETRY_DELAY, MAX_RETRY_DELAY);
+    let mut interval = tokio::time::interval(STATE_INTERVAL);
+    let mut published_state = None;
+    // Whether the subscription, discovery config and availability were sent since connecting
+    let mut announced = true;
+
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    backoff.reset();
+                    announced = false;
+                    published_state = None;
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                    if let Err(e) = apply_command(&config, &mut global_state.lock().unwrap(), &publish.payload) {
+                        println!("Invalid MQTT command: {e}");
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    println!("MQTT connection failed, reconnecting in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                }
+            },
+            _ = interval.tick() => {
+                let state = State::new(&global_state.lock().unwrap());
+                if published_state.as_ref() != Some(&state) {
+                    let payload = json!(state).to_string();
+                    // Retried on the next tick if the request queue is full while disconnected
+                    if client.try_publish(&state_topic, QoS::AtLeastOnce, true, payload).is_ok() {
+                        published_state = Some(state);
+                    }
+                }
+            }
+        }
+
+        // Outside of the select, as awaiting a full request queue would keep the eventloop from
+        // being polled to empty it
+        if !announced {
+            match announce(&client, &config) {
+                Ok(()) => announced = true,
+                Err(e) => println!("Failed to announce over MQTT, retrying: {e}"),
+            }
+        }
+    }
+}
+
+/// Subscribes to commands and tells Home Assistant about the light, without waiting for room in
+/// the request queue.
+fn announce(client: &AsyncClient, config: &MqttConfig) -> Result<(), ClientError> {
+    client.try_subscribe(topic(config, "set"), QoS::AtLeastOnce)?;
+    client.try_publish(
+        discovery_topic(config),
+        QoS::AtLeastOnce,
+        true,
+        discovery_config(config).to_string(),
+    )?;
+    client.try_publish(
+        topic(config, "availability"),
+        QoS::AtLeastOnce,
+        true,
+        "online",
+    )
+}
+
+fn topic(config: &MqttConfig, name: &str) -> String {
+    format!("home-leds/{}/{name}", config.node_id)
+}
+
+fn discovery_topic(config: &MqttConfig) -> String {
+    format!(
+        "{}/light/{}/config",
+        config.discovery_prefix, config.node_id
+    )
+}
+
+/// The discovery config that makes Home Assistant show the strip as a JSON schema light.
+fn discovery_config(config: &MqttConfig) -> Value {
+    json!({
+        "name": config.name,
+        "unique_id": config.node_id,
+        "schema": "json",
+        "command_topic": topic(config, "set"),
+        "state_topic": topic(config, "state"),
+        "availability_topic": topic(config, "availability"),
+        "brightness": true,
+        "supported_color_modes": ["rgb"],
+        "effect": true,
+        "effect_list": effect::names(),
+        "device": {
+            "identifiers": [config.node_id],
+            "name": config.name,
+        },
+    })
+}
+
+fn apply_command(
+    config: &MqttConfig,
+    global_state: &mut GlobalState,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    let command: Command = serde_json::from_slice(payload)?;
+
+    if let Some(effect) = &command.effect {
+        if effect::find(effect).is_none() {
+            anyhow::bail!("Unknown effect {effect:?}");
+        }
+    }
+
+    let timeout = command
+        .timeout
+        .map(|timeout| {
+            Duration::try_from_secs_f32(timeout)
+                .map_err(|_| anyhow::anyhow!("Invalid timeout {timeout}"))
+        })
+        .transpose()?;
+
+    match command.state.as_deref() {
+        Some("ON") => global_state.on = true,
+        Some("OFF") => global_state.on = false,
+        Some(state) => anyhow::bail!("Unknown state {state:?}"),
+        None => {}
+    }
+
+    if let Some(brightness) = command.brightness {
+        global_state.brightness = brightness;
+    }
+
+    if let Some(color) = command.color {
+        global_state
+            .priorities
+            .set(SOURCE, config.priority, color, timeout);
+    }
+
+    if let Some(effect) = command.effect {
+        global_state.effect = effect;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time::{sleep, timeout},
+    };
+
+    use super::*;
+
+    fn config() -> MqttConfig {
+        MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            discovery_prefix: "homeassistant".to_string(),
+            node_id: "desk".to_string(),
+            name: "Desk LEDs".to_string(),
+            priority: 100,
+        }
+    }
+
+    #[test]
+    fn discovery_config_describes_json_light() {
+        let config = config();
+
+        assert_eq!(discovery_topic(&config), "homeassistant/light/desk/config");
+
+        let discovery = discovery_config(&config);
+        assert_eq!(discovery["schema"], "json");
+        assert_eq!(discovery["unique_id"], "desk");
+        assert_eq!(discovery["command_topic"], "home-leds/desk/set");
+        assert_eq!(discovery["state_topic"], "home-leds/desk/state");
+        assert_eq!(discovery["supported_color_modes"], json!(["rgb"]));
+        assert_eq!(discovery["effect_list"], json!(effect::names()));
+    }
+
+    #[test]
+    fn applies_commands() {
+        let config = config();
+        let mut global_state = GlobalState::new("stars", &[]);
+
+        apply_command(
+            &config,
+            &mut global_state,
+            br#"{"state":"ON","brightness":128,"color":{"r":255,"g":100,"b":0},"effect":"solid"}"#,
+        )
+        .unwrap();
+
+        assert!(global_state.on);
+        assert_eq!(global_state.brightness, 128);
+        assert_eq!(global_state.color(), Rgb::from([255, 100, 0]));
+        assert_eq!(global_state.effect, "solid");
+
+        apply_command(&config, &mut global_state, br#"{"state":"OFF"}"#).unwrap();
+
+        assert!(!global_state.on);
+        assert_eq!(global_state.brightness, 128);
+
+        let state = serde_json::to_value(State::new(&global_state)).unwrap();
+        assert_eq!(
+            state,
+            json!({
+                "state": "OFF",
+                "brightness": 128,
+                "color_mode": "rgb",
+                "color": { "r": 255, "g": 100, "b": 0 },
+                "effect": "solid",
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_effect() {
+        let mut global_state = GlobalState::new("stars", &[]);
+
+        assert!(apply_command(
+            &config(),
+            &mut global_state,
+            br#"{"state":"OFF","effect":"disco"}"#
+        )
+        .is_err());
+        assert!(global_state.on);
+        assert_eq!(global_state.effect, "stars");
+
+        assert!(apply_command(&config(), &mut global_state, br#"{"timeout":-1}"#).is_err());
+    }
+
+    #[test]
+    fn colours_with_timeout_expire() {
+        let config = config();
+        let mut global_state = GlobalState::new("solid", &[]);
+        global_state
+            .priorities
+            .set("home_assistant", 100, Rgb::from([0, 0, 255]), None);
+
+        apply_command(
+            &config,
+            &mut global_state,
+            br#"{"color":{"r":255,"g":0,"b":0},"timeout":0.02}"#,
+        )
+        .unwrap();
+        assert_eq!(global_state.color(), Rgb::from([255, 0, 0]));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(global_state.color(), Rgb::from([0, 0, 255]));
+    }
+
+    /// Reads a packet on the broker's side, returning its type and its body.
+    async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let header = stream.read_u8().await.unwrap();
+
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            let byte = stream.read_u8().await.unwrap();
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.unwrap();
+
+        (header >> 4, body)
+    }
+
+    /// The length-prefixed topic at the start of `data`.
+    fn read_topic(data: &[u8]) -> String {
+        let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+        String::from_utf8(data[2..2 + len].to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn announces_and_follows_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            ..config()
+        };
+        let global_state = Arc::new(Mutex::new(GlobalState::new("stars", &[])));
+        tokio::spawn(run(config, global_state.clone()));
+
+        let broker = async {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let (kind, _) = read_packet(&mut stream).await;
+            assert_eq!(kind, 1, "expected CONNECT");
+            stream.write_all(&[0x20, 2, 0, 0]).await.unwrap();
+
+            let mut subscriptions = Vec::new();
+            let mut published = Vec::new();
+            while !(subscriptions.contains(&"home-leds/desk/set".to_string())
+                && published.contains(&"homeassistant/light/desk/config".to_string())
+                && published.contains(&"home-leds/desk/availability".to_string()))
+            {
+                match read_packet(&mut stream).await {
+                    // Topics follow the packet id
+                    (8, body) => subscriptions.push(read_topic(&body[2..])),
+                    (3, body) => published.push(read_topic(&body)),
+                    _ => {}
+                }
+            }
+
+            let topic = b"home-leds/desk/set";
+            let payload = br#"{"effect":"solid"}"#;
+            let mut packet = vec![0x30, (2 + topic.len() + payload.len()) as u8, 0];
+            packet.push(topic.len() as u8);
+            packet.extend_from_slice(topic);
+            packet.extend_from_slice(payload);
+            stream.write_all(&packet).await.unwrap();
+
+            while global_state.lock().unwrap().effect != "solid" {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+
+        timeout(Duration::from_secs(5), broker).await.unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use home_leds_protocol::{
+    ddp,
+    packet::{self, Header, HEADER_LEN},
+    wled, PixelFormat,
+};
+use serde::Deserialize;
+
+use crate::{device, discovery, OutputConfig};
+
+/// Wire format frames are sent to the strip in.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// The firmware's own packets, see [`home_leds_protocol::packet`].
+    #[default]
+    Native,
+    /// Distributed Display Protocol, understood by WLED and others.
+    Ddp,
+    /// WLED's realtime protocol, in DNRGB mode.
+    Wled,
+}
+
+/// Seconds a WLED device keeps showing our frames for if we stop sending, before returning to its
+/// own effects.
+const WLED_TIMEOUT_SECS: u8 = 2;
+
+impl Protocol {
+    /// UDP port devices listen on for this protocol, if the address doesn't specify one.
+    pub fn default_port(self) -> u16 {
+        match self {
+            Protocol::Native => packet::PORT,
+            Protocol::Ddp => ddp::PORT,
+            Protocol::Wled => wled::PORT,
+        }
+    }
+
+    /// Resolves `address`, which may leave out the port to use the protocol's default one.
+    pub fn resolve(self, address: &str) -> io::Result<SocketAddr> {
+        let mut addrs = match address.to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(_) => (address, self.default_port()).to_socket_addrs()?,
+        };
+
+        addrs
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))
+    }
+
+    /// Wraps RGB pixel data for the whole strip into packets, fragmenting it if it doesn't fit in
+    /// a single one.
+    pub fn packets(self, sequence: u8, pixels: &[u8]) -> Vec<Vec<u8>> {
+        match self {
+            Protocol::Native => native_packets(sequence, pixels),
+            Protocol::Ddp => ddp_packets(sequence, pixels),
+            Protocol::Wled => wled_packets(pixels),
+        }
+    }
+}
+
+/// A strip showing a region of the canvas.
+pub struct Output {
+    protocol: Protocol,
+    /// Kept up to date by [`discovery::follow`] if the strip was found with mDNS.
+    address: Arc<Mutex<SocketAddr>>,
+    /// Pixels of the canvas shown on the strip.
+    pub range: Range<usize>,
+    reverse: bool,
+    sequence: u8,
+    /// Whether the last frame failed to send, so errors are logged once rather than every frame.
+    failing: bool,
+}
+
+impl Output {
+    /// Finds the strip, pushes its device settings and starts following it if it was found with
+    /// mDNS.
+    pub async fn connect(config: &OutputConfig) -> anyhow::Result<Self> {
+        let (address, found) =
+            match config.address.as_deref() {
+                Some(address) if discovery::mdns_name(address).is_none() => {
+                    (config.protocol.resolve(address)?, None)
+                }
+                address => {
+                    let (name, port) = address.and_then(discovery::mdns_name).unzip();
+                    let device = discovery::find(name).await?.into_iter().next().ok_or_else(
+                        || match name {
+                            Some(name) => anyhow::anyhow!("{name}.local not found"),
+                            None => anyhow::anyhow!("No strips found, set an address"),
+                        },
+                    )?;
+
+                    println!("Found {} at {}", device.name, device.ip);
+
+                    // Only native packets are advertised, the rest are on their usual ports,
+                    // unless given along with the name
+                    let port = match (port.flatten(), config.protocol) {
+                        (Some(port), _) => port,
+                        (None, Protocol::Native) => device.port,
+                        (None, protocol) => protocol.default_port(),
+                    };
+
+                    (SocketAddr::new(device.ip, port), Some(device))
+                }
+            };
+
+        let leds = config
+            .leds
+            .or(found.as_ref().and_then(|device| device.leds))
+            .ok_or_else(|| anyhow::anyhow!("Unknown number of LEDs at {address}, set leds"))?;
+
+        if let Some(device) = &config.device {
+            if let Err(e) = device::configure(address.ip(), leds, device) {
+                println!("Failed to configure device at {address}: {e}");
+            }
+        }
+
+        let address = Arc::new(Mutex::new(address));
+        if let Some(device) = found {
+            tokio::spawn(discovery::follow(device, address.clone()));
+        }
+
+        Ok(Output {
+            protocol: config.protocol,
+            address,
+            range: config.start..config.start + leds,
+            reverse: config.reverse,
+            sequence: 0,
+            failing: false,
+        })
+    }
+
+    /// Sends the strip its region of `pixels`, RGB data for the whole canvas. Errors are logged
+    /// rather than returned, so one unreachable strip doesn't stop the others.
+    pub fn send(&mut self, socket: &UdpSocket, pixels: &[u8]) {
+        let address = *self.address.lock().unwrap();
+
+        let result = self.try_send(socket, address, pixels);
+        self.sequence = self.sequence.wrapping_add(1);
+
+        match (&result, self.failing) {
+            (Err(e), false) => println!("Failed to send to {address}, retrying: {e}"),
+            (Ok(()), true) => println!("Sending to {address} again"),
+            _ => {}
+        }
+        self.failing = result.is_err();
+    }
+
+    fn try_send(&self, socket: &UdpSocket, address: SocketAddr, pixels: &[u8]) -> io::Result<()> {
+        for packet in self.protocol.packets(self.sequence, &self.region(pixels)) {
+            socket.send_to(&packet, address)?;
+        }
+
+        Ok(())
+    }
+
+    fn region(&self, pixels: &[u8]) -> Vec<u8> {
+        let region = &pixels[self.range.start * 3..self.range.end * 3];
+
+        if self.reverse {
+            region.chunks_exact(3).rev().flatten().copied().collect()
+        } else {
+            region.to_vec()
+        }
+    }
+}
+
+fn native_packets(sequence: u8, pixels: &[u8]) -> Vec<Vec<u8>> {
+    packet::fragments(pixels, PixelFormat::Rgb8)
+        .map(|(offset, pixels, flags)| {
+            let header = Header {
+                sequence,
+                flags,
+                format: PixelFormat::Rgb8,
+                offset,
+            };
+
+            let mut packet = vec![0; HEADER_LEN + pixels.len()];
+            header
+                .encode(pixels, &mut packet)
+                .expect("packet is sized to fit the pixels");
+
+            packet
+        })
+        .collect()
+}
+
+fn ddp_packets(sequence: u8, pixels: &[u8]) -> Vec<Vec<u8>> {
+    ddp::fragments(pixels)
+        .map(|(offset, data, push)| {
+            let header = ddp::Header {
+                flags: if push { ddp::FLAG_PUSH } else { 0 },
+                // DDP sequence numbers run from 1 to 15
+                sequence: sequence % 15 + 1,
+                format: PixelFormat::Rgb8,
+                destination: ddp::ID_DISPLAY,
+                offset,
+            };
+
+            let mut packet = vec![0; ddp::HEADER_LEN + data.len()];
+            header
+                .encode(data, &mut packet)
+                .expect("packet is sized to fit the data");
+
+            packet
+        })
+        .collect()
+}
+
+fn wled_packets(pixels: &[u8]) -> Vec<Vec<u8>> {
+    wled::dnrgb_fragments(pixels)
+        .map(|(offset, data)| {
+            let mut packet = vec![0; wled::DNRGB_HEADER_LEN + data.len()];
+            wled::encode_dnrgb(WLED_TIMEOUT_SECS, offset, data, &mut packet)
+                .expect("packet is sized to fit the data");
+
+            packet
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(range: Range<usize>, reverse: bool) -> Output {
+        Output {
+            protocol: Protocol::Native,
+            address: Arc::new(Mutex::new(([127, 0, 0, 1], packet::PORT).into())),
+            range,
+            reverse,
+            sequence: 0,
+            failing: false,
+        }
+    }
+
+    #[test]
+    fn sends_its_region_of_the_canvas() {
+        let pixels = [1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4];
+
+        assert_eq!(output(1..3, false).region(&pixels), [2, 2, 2, 3, 3, 3]);
+        assert_eq!(
+            output(1..4, true).region(&pixels),
+            [4, 4, 4, 3, 3, 3, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn keeps_going_after_failing_to_send() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pixels = [1, 1, 1];
+
+        let mut output = output(0..1, false);
+        // Port 0 can't be sent to
+        *output.address.lock().unwrap() = ([127, 0, 0, 1], 0).into();
+        output.send(&socket, &pixels);
+        assert!(output.failing);
+
+        *output.address.lock().unwrap() = receiver.local_addr().unwrap();
+        output.send(&socket, &pixels);
+        assert!(!output.failing);
+        assert_eq!(output.sequence, 2);
+    }
+}
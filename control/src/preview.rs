@@ -0,0 +1,127 @@
+//! Drawing frames in the terminal with 24-bit colour, for working on effects without a strip.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write as _},
+};
+
+use terminal_size::{terminal_size, Width};
+
+use crate::layout::{self, LayoutConfig};
+
+/// Columns to wrap at if the terminal's width can't be found, like when output is piped.
+const DEFAULT_WIDTH: usize = 80;
+
+/// How LEDs are placed in cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Grid {
+    /// In strip order, wrapping at the terminal's width.
+    Wrapped,
+    /// Where they are in a matrix layout.
+    Matrix { width: usize, serpentine: bool },
+}
+
+/// Draws every frame over the previous one, one cell per LED.
+pub struct Preview {
+    grid: Grid,
+    out: String,
+}
+
+impl Preview {
+    pub fn new(layout: &LayoutConfig) -> Self {
+        let grid = match *layout {
+            LayoutConfig::Matrix { width, serpentine } if width > 0 => {
+                Grid::Matrix { width, serpentine }
+            }
+            _ => Grid::Wrapped,
+        };
+
+        Preview {
+            grid,
+            out: String::new(),
+        }
+    }
+
+    /// Draws RGB pixel data for the whole canvas.
+    pub fn draw(&mut self, pixels: &[u8]) -> io::Result<()> {
+        let width = terminal_size().map_or(DEFAULT_WIDTH, |(Width(width), _)| width as usize);
+
+        self.out.clear();
+        // Back to the top left, rather than clearing, to avoid flickering
+        self.out.push_str("\x1b[H");
+        render(&mut self.out, pixels, self.grid, width);
+        // Whatever was below a previous, taller frame
+        self.out.push_str("\x1b[J");
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// Writes a line of coloured cells to `out` for every row of the grid.
+fn render(out: &mut String, pixels: &[u8], grid: Grid, terminal_width: usize) {
+    let leds = pixels.len() / 3;
+
+    let (width, serpentine) = match grid {
+        Grid::Wrapped => (terminal_width.max(1), false),
+        Grid::Matrix { width, serpentine } => (width, serpentine),
+    };
+
+    let mut cells = vec![None; leds.div_ceil(width) * width];
+    for (index, pixel) in pixels.chunks_exact(3).enumerate() {
+        let (column, row) = layout::grid_position(index, width, serpentine);
+        cells[row * width + column] = Some(pixel);
+    }
+
+    for row in cells.chunks(width) {
+        for cell in row {
+            match cell {
+                Some(&[r, g, b]) => write!(out, "\x1b[38;2;{r};{g};{b}m█").unwrap(),
+                _ => out.push_str("\x1b[0m "),
+            }
+        }
+
+        // Reset before clearing the rest of the line, so it isn't coloured in
+        out.push_str("\x1b[0m\x1b[K\r\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_to_the_terminal() {
+        let mut out = String::new();
+        render(
+            &mut out,
+            &[255, 0, 0, 0, 0, 255, 0, 255, 0],
+            Grid::Wrapped,
+            2,
+        );
+
+        assert_eq!(
+            out,
+            "\x1b[38;2;255;0;0m█\x1b[38;2;0;0;255m█\x1b[0m\x1b[K\r\n\
+             \x1b[38;2;0;255;0m█\x1b[0m \x1b[0m\x1b[K\r\n"
+        );
+    }
+
+    #[test]
+    fn follows_the_matrix() {
+        let grid = Grid::Matrix {
+            width: 2,
+            serpentine: true,
+        };
+
+        let mut out = String::new();
+        render(&mut out, &[1, 1, 1, 2, 2, 2, 3, 3, 3], grid, 80);
+
+        assert_eq!(
+            out,
+            "\x1b[38;2;1;1;1m█\x1b[38;2;2;2;2m█\x1b[0m\x1b[K\r\n\
+             \x1b[0m \x1b[38;2;3;3;3m█\x1b[0m\x1b[K\r\n"
+        );
+    }
+}
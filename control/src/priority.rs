@@ -0,0 +1,141 @@
+//! Deciding which of the inputs setting the colour wins, like Hyperion's priorities: the live input
+//! with the highest priority drives the strip, and when it expires the next one takes over.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::color::Rgb;
+
+/// Priority of inputs that don't have one configured.
+pub const DEFAULT_PRIORITY: u8 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Input {
+    /// Higher priorities win.
+    pub priority: u8,
+    pub color: Rgb,
+    /// When the input stops counting, unless it's set again before then.
+    pub expires: Option<Instant>,
+    set: Instant,
+}
+
+impl Input {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+}
+
+/// The colour set by every input, by name.
+#[derive(Debug, Default)]
+pub struct Priorities {
+    inputs: HashMap<String, Input>,
+}
+
+impl Priorities {
+    /// Sets the colour of `source`, replacing whatever it set before. With a `timeout`, the input
+    /// expires after that long.
+    ///
+    /// Setting the same colour and priority again only renews it, so inputs that repeat their
+    /// state, like Home Assistant does when polled, don't take over from others at their priority.
+    pub fn set(&mut self, source: &str, priority: u8, color: Rgb, timeout: Option<Duration>) {
+        let now = Instant::now();
+        self.inputs.retain(|_, input| input.is_live(now));
+
+        let set = match self.inputs.get(source) {
+            Some(input) if input.priority == priority && input.color == color => input.set,
+            _ => now,
+        };
+
+        self.inputs.insert(
+            source.to_string(),
+            Input {
+                priority,
+                color,
+                expires: timeout.map(|timeout| now + timeout),
+                set,
+            },
+        );
+    }
+
+    /// Removes the input of `source`, falling back to the next one.
+    pub fn clear(&mut self, source: &str) {
+        self.inputs.remove(source);
+    }
+
+    /// The live input with the highest priority, the most recently set one if several share it.
+    pub fn active(&self) -> Option<(&str, &Input)> {
+        let now = Instant::now();
+
+        self.inputs
+            .iter()
+            .filter(|(_, input)| input.is_live(now))
+            .max_by_key(|(_, input)| (input.priority, input.set))
+            .map(|(source, input)| (source.as_str(), input))
+    }
+
+    /// The colour of the active input, if any.
+    pub fn color(&self) -> Option<Rgb> {
+        self.active().map(|(_, input)| input.color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb = Rgb { r: 255, g: 0, b: 0 };
+    const BLUE: Rgb = Rgb { r: 0, g: 0, b: 255 };
+
+    #[test]
+    fn highest_priority_wins() {
+        let mut priorities = Priorities::default();
+        assert_eq!(priorities.color(), None);
+
+        priorities.set("override", 200, RED, None);
+        priorities.set("home_assistant", 100, BLUE, None);
+        assert_eq!(priorities.active().unwrap().0, "override");
+        assert_eq!(priorities.color(), Some(RED));
+
+        // Ties go to the latest
+        priorities.set("mqtt", 100, RED, None);
+        priorities.set("override", 50, RED, None);
+        assert_eq!(priorities.active().unwrap().0, "mqtt");
+        priorities.set("home_assistant", 100, RED, None);
+        assert_eq!(priorities.active().unwrap().0, "home_assistant");
+    }
+
+    #[test]
+    fn repeated_colours_keep_their_place() {
+        let mut priorities = Priorities::default();
+
+        priorities.set("home_assistant", 100, BLUE, None);
+        priorities.set("api", 100, RED, None);
+
+        // Home Assistant reporting the same state again
+        priorities.set("home_assistant", 100, BLUE, None);
+        assert_eq!(priorities.active().unwrap().0, "api");
+        assert_eq!(priorities.color(), Some(RED));
+
+        // But a new colour does take over
+        priorities.set("home_assistant", 100, RED, None);
+        assert_eq!(priorities.active().unwrap().0, "home_assistant");
+    }
+
+    #[test]
+    fn falls_back_when_expired() {
+        let mut priorities = Priorities::default();
+
+        priorities.set("home_assistant", 100, BLUE, None);
+        priorities.set("notification", 250, RED, Some(Duration::from_millis(20)));
+        assert_eq!(priorities.color(), Some(RED));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(priorities.color(), Some(BLUE));
+
+        // Expired inputs are dropped when the next one is set
+        priorities.set("home_assistant", 100, BLUE, None);
+        assert_eq!(priorities.inputs.len(), 1);
+    }
+}
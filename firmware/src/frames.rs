@@ -0,0 +1,103 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+use crate::MAX_LEDS;
+
+/// The latest complete frames, latched by the receive task and shown by the output task.
+pub struct FrameStore {
+    frames: Mutex<CriticalSectionRawMutex, RefCell<Frames>>,
+}
+
+struct Frames {
+    /// The latest frame and the one before it, to interpolate between.
+    buffers: [[RGB8; MAX_LEDS]; 2],
+    current: usize,
+    len: usize,
+    received: Option<Instant>,
+    /// Time between the previous frame and the current one.
+    interval: Duration,
+    /// How long the current frame is shown for without a new one.
+    timeout: Duration,
+}
+
+impl FrameStore {
+    pub const fn new() -> Self {
+        FrameStore {
+            frames: Mutex::new(RefCell::new(Frames {
+                buffers: [[RGB8 { r: 0, g: 0, b: 0 }; MAX_LEDS]; 2],
+                current: 0,
+                len: 0,
+                received: None,
+                interval: Duration::from_ticks(0),
+                timeout: Duration::from_ticks(0),
+            })),
+        }
+    }
+
+    /// Latches a complete frame, to be shown for at most `timeout` if no new one arrives.
+    pub fn push(&self, frame: &[RGB8], timeout: Duration) {
+        let now = Instant::now();
+
+        self.frames.lock(|frames| {
+            let mut frames = frames.borrow_mut();
+
+            let next = 1 - frames.current;
+            frames.buffers[next][..frame.len()].copy_from_slice(frame);
+            frames.current = next;
+            frames.len = frame.len();
+
+            frames.interval = frames
+                .received
+                .map_or(Duration::from_ticks(0), |received| now - received);
+            frames.received = Some(now);
+            frames.timeout = timeout;
+        });
+    }
+
+    /// Writes the frame to show at `now` to `out`, returning `false` if the latest frame timed
+    /// out.
+    ///
+    /// With `interpolate`, this fades from the previous frame to the latest one over the time
+    /// between them, which delays the output by a frame but hides dropped ones.
+    pub fn render(&self, now: Instant, interpolate: bool, out: &mut [RGB8; MAX_LEDS]) -> bool {
+        self.frames.lock(|frames| {
+            let frames = frames.borrow();
+
+            let Some(received) = frames.received else {
+                return false;
+            };
+
+            let age = now.saturating_duration_since(received);
+            if age > frames.timeout {
+                return false;
+            }
+
+            let current = &frames.buffers[frames.current][..frames.len];
+            let previous = &frames.buffers[1 - frames.current][..frames.len];
+
+            // Weight of the current frame, out of 256
+            let weight = if !interpolate || age >= frames.interval {
+                256
+            } else {
+                (age.as_ticks() * 256 / frames.interval.as_ticks()) as u16
+            };
+
+            for ((out, &current), &previous) in out.iter_mut().zip(current).zip(previous) {
+                *out = RGB8 {
+                    r: mix(previous.r, current.r, weight),
+                    g: mix(previous.g, current.g, weight),
+                    b: mix(previous.b, current.b, weight),
+                };
+            }
+
+            true
+        })
+    }
+}
+
+fn mix(from: u8, to: u8, weight: u16) -> u8 {
+    ((from as u16 * (256 - weight) + to as u16 * weight) >> 8) as u8
+}
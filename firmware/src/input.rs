@@ -0,0 +1,183 @@
+use core::{future::poll_fn, task::Poll};
+
+use embassy_net::udp::UdpSocket;
+use embassy_time::Duration;
+use home_leds_protocol::{
+    artnet, ddp,
+    dmx::{FrameTracker, UniverseMap},
+    e131,
+    packet::{Header, Reassembly},
+    settings::Settings,
+    wled, Pixels,
+};
+
+/// Protocols LED data is accepted in.
+#[derive(Debug, Clone, Copy)]
+pub enum Protocol {
+    Native,
+    Ddp,
+    E131,
+    ArtNet,
+    Wled,
+}
+
+/// A socket receiving LED data in one protocol.
+pub struct Input {
+    pub protocol: Protocol,
+    pub socket: UdpSocket<'static>,
+}
+
+/// Pixel data received in any protocol.
+pub struct Update<'a> {
+    pub pixels: Pixels<'a>,
+    /// Whether this completes a frame that should now be shown.
+    pub push: bool,
+    /// How long to keep showing received frames without new packets, if the sender says.
+    pub timeout: Option<Duration>,
+}
+
+/// Waits for a packet on any of `inputs`, returning its protocol and length.
+pub async fn receive(inputs: &[Input], buf: &mut [u8]) -> (Protocol, usize) {
+    poll_fn(|cx| {
+        for input in inputs {
+            loop {
+                match input.socket.poll_recv_from(buf, cx) {
+                    Poll::Ready(Ok((n, _))) => return Poll::Ready((input.protocol, n)),
+                    // Truncated, try the next one
+                    Poll::Ready(Err(_)) => continue,
+                    Poll::Pending => break,
+                }
+            }
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+/// Decodes packets of any protocol, keeping track of state across packets.
+#[derive(Default)]
+pub struct Decoder {
+    reassembly: Reassembly,
+    e131_frames: FrameTracker,
+    artnet_frames: FrameTracker,
+}
+
+impl Decoder {
+    /// Decodes a packet, mapping DMX universes onto the strip as `settings` say.
+    pub fn decode<'a>(
+        &mut self,
+        protocol: Protocol,
+        packet: &'a [u8],
+        settings: &Settings,
+    ) -> Option<Update<'a>> {
+        let leds = settings.leds as usize;
+
+        match protocol {
+            Protocol::Native => {
+                let (header, data) = Header::decode(packet)
+                    .map_err(|e| log::warn!("Invalid packet: {:?}", e))
+                    .ok()?;
+                let pixel_count = data.len() / header.format.bytes_per_pixel();
+
+                Some(Update {
+                    pixels: Pixels::Range {
+                        offset: header.offset as usize,
+                        format: header.format,
+                        data,
+                    },
+                    push: self.reassembly.receive(&header, pixel_count),
+                    timeout: None,
+                })
+            }
+            Protocol::Ddp => {
+                let (header, data) = ddp::Header::decode(packet)
+                    .map_err(|e| log::warn!("Invalid DDP packet: {:?}", e))
+                    .ok()?;
+
+                if !header.is_display() {
+                    return None;
+                }
+
+                Some(Update {
+                    pixels: Pixels::Range {
+                        offset: header.offset as usize / header.format.bytes_per_pixel(),
+                        format: header.format,
+                        data,
+                    },
+                    push: header.push(),
+                    timeout: None,
+                })
+            }
+            Protocol::E131 => {
+                let packet = e131::Packet::decode(packet)
+                    .map_err(|e| log::debug!("Ignoring E1.31 packet: {:?}", e))
+                    .ok()?;
+
+                if packet.options & (e131::OPTION_PREVIEW_DATA | e131::OPTION_STREAM_TERMINATED)
+                    != 0
+                {
+                    return None;
+                }
+
+                dmx_update(
+                    &settings.universe_map(e131::FIRST_UNIVERSE),
+                    &mut self.e131_frames,
+                    packet.universe,
+                    packet.data,
+                    leds,
+                )
+            }
+            Protocol::ArtNet => {
+                let packet = artnet::Packet::decode(packet)
+                    .map_err(|e| log::debug!("Ignoring Art-Net packet: {:?}", e))
+                    .ok()?;
+
+                dmx_update(
+                    &settings.universe_map(artnet::FIRST_UNIVERSE),
+                    &mut self.artnet_frames,
+                    packet.universe,
+                    packet.data,
+                    leds,
+                )
+            }
+            Protocol::Wled => {
+                let packet = wled::Packet::decode(packet)
+                    .map_err(|e| log::debug!("Ignoring WLED packet: {:?}", e))
+                    .ok()?;
+
+                // WLED shows every packet as it arrives
+                Some(Update {
+                    pixels: packet.pixels,
+                    push: true,
+                    timeout: Some(
+                        packet
+                            .timeout_secs()
+                            .map_or(Duration::MAX, |secs| Duration::from_secs(secs as u64)),
+                    ),
+                })
+            }
+        }
+    }
+}
+
+fn dmx_update<'a>(
+    map: &UniverseMap,
+    frames: &mut FrameTracker,
+    universe: u16,
+    data: &'a [u8],
+    leds: usize,
+) -> Option<Update<'a>> {
+    // Checked before the frame tracker sees it, so universes off the strip don't hold back frames
+    let offset = map.offset(universe, leds)?;
+
+    Some(Update {
+        pixels: Pixels::Range {
+            offset,
+            format: map.format,
+            data: map.pixel_data(data),
+        },
+        push: frames.receive(universe),
+        timeout: None,
+    })
+}
@@ -0,0 +1,25 @@
+use home_leds_protocol::settings::LocalEffect;
+use smart_leds::{
+    hsv::{hsv2rgb, Hsv},
+    RGB8,
+};
+
+/// Renders frame number `tick` of an effect shown on the strip itself while no frames arrive over
+/// the network.
+pub fn render(effect: LocalEffect, tick: u32, frame: &mut [RGB8]) {
+    match effect {
+        LocalEffect::Rainbow => {
+            let hue = (tick as u8).wrapping_neg();
+
+            for (i, led) in frame.iter_mut().enumerate() {
+                *led = hsv2rgb(Hsv {
+                    hue: hue.wrapping_add(i as u8 * 2),
+                    sat: 255,
+                    val: 255,
+                });
+            }
+        }
+        LocalEffect::Solid([r, g, b]) => frame.fill(RGB8::new(r, g, b)),
+        LocalEffect::Off => frame.fill(RGB8::default()),
+    }
+}
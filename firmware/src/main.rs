@@ -10,32 +10,32 @@
 // TEMP
 #![allow(unused)]
 
-// const LEDS: usize = 100;
+/// Longest strip that can be driven. The SPI driver needs 12 bytes of RAM per LED, and sending
+/// takes 30 µs per LED, so a whole strip still fits in a frame at `FRAME_RATE`.
+const MAX_LEDS: usize = 512;
 
-// fn led_position(idx: u8) -> (f32, f32) {
-//     (idx as f32, 0.)
-//     // let mut x = idx % 14;
-//     // let y = idx / 14;
-//     // if y % 2 == 0 {
-//     //     x = 13 - x;
-//     // }
+const HEAP_SIZE: usize = 128 * 1024;
 
-//     // (x as f32, y as f32)
-// }
-
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
+/// Failed attempts to connect to the stored network before starting provisioning mode.
+const MAX_CONNECT_ATTEMPTS: u32 = 5;
 
-const MDNS_ADDR: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
-const MDNS_PORT: u16 = 5353;
-const MDNS_NAME: &str = concat!(env!("HOSTNAME"), ".local");
+/// How long to keep showing the last received frame before falling back to a local effect.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(5);
 
-const LEDS_PORT: u16 = 7777;
+/// Rate at which frames are written to the strip, regardless of how fast they're received.
+const FRAME_RATE: u64 = 60;
 
-const HEAP_SIZE: usize = 64 * 1024;
+/// How often the mDNS responder checks whether its records changed and need announcing.
+const MDNS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 //
 
+mod frames;
+mod input;
+mod local;
+mod ota;
+mod provisioning;
+mod settings;
 mod ws2812_driver;
 
 extern crate alloc;
@@ -44,44 +44,48 @@ use alloc::boxed::Box;
 use core::{
     future::pending,
     mem::{self, MaybeUninit},
-    num::Wrapping,
 };
 
-use dnsparse::{Answer, HeaderKind, QueryClass, QueryKind};
 use embassy_executor::{raw::TaskStorage, Spawner};
 use embassy_net::{
     udp::{PacketMetadata, UdpSocket},
     Config, IpListenEndpoint, Ipv4Address, Stack, StackResources,
 };
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
+use embedded_hal_async::spi::SpiBus;
 use embedded_svc::wifi::{ClientConfiguration, Configuration, Wifi};
 use esp32c3_hal::{
     clock::ClockControl,
+    dma::DmaPriority,
     embassy,
-    gpio::OutputPin,
-    peripheral::Peripheral,
+    gdma::Gdma,
     peripherals::Peripherals,
     prelude::*,
-    rmt::{TxChannelConfig, TxChannelCreator},
+    spi::{
+        master::{dma::WithDmaSpi2, Spi},
+        SpiMode,
+    },
     timer::TimerGroup,
-    Rmt, Rng, IO,
+    Rng, IO,
 };
 use esp_backtrace as _;
 use esp_hal_common::peripherals::Interrupt;
 use esp_wifi::{
     initialize,
-    wifi::{WifiController, WifiDevice, WifiEvent, WifiStaDevice, WifiState},
+    wifi::{WifiApDevice, WifiController, WifiDevice, WifiEvent, WifiStaDevice, WifiState},
     EspWifiInitFor,
 };
 use futures_util::Future;
-use smart_leds::{
-    gamma,
-    hsv::{self, Hsv},
-    SmartLedsWrite, RGB8,
+use home_leds_protocol::{artnet, ddp, e131, mdns, packet, settings::MAX_MESSAGE_LEN, wled};
+use smart_leds::{gamma, RGB8};
+
+use crate::{
+    frames::FrameStore,
+    input::{Decoder, Input, Protocol},
+    settings::{Credentials, SharedSettings},
+    ws2812_driver::{SpiWs2812, SPI_FREQUENCY_KHZ},
 };
 
-use crate::ws2812_driver::RmtWs2812;
-
 // defmt::timestamp!("{=u64:us}\t", Instant::now().as_micros());
 
 // #[defmt::panic_handler]
@@ -103,6 +107,25 @@ macro_rules! make_static {
     }};
 }
 
+/// Creates a UDP socket bound to `$port`, with room for `$packets` received packets of up to
+/// `$len` bytes each.
+macro_rules! udp_socket {
+    ($stack:expr, $port:expr, $packets:literal, $len:expr) => {{
+        let rx_meta = make_static!([PacketMetadata::EMPTY; $packets]);
+        let rx_buffer = make_static!([0; $packets * $len]);
+        let tx_meta = make_static!([PacketMetadata::EMPTY; 8]);
+        let tx_buffer = make_static!([0; 1500]);
+
+        let mut socket = UdpSocket::new($stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+
+        socket
+            .bind(IpListenEndpoint { addr: None, port: $port })
+            .unwrap();
+
+        socket
+    }};
+}
+
 #[global_allocator]
 static ALLOCATOR: esp_alloc::EspHeap = esp_alloc::EspHeap::empty();
 
@@ -137,6 +160,12 @@ async fn main(spawner: Spawner) -> ! {
 
     init_heap();
 
+    // Rolls back and restarts if an update failed to confirm itself last time
+    ota::check_boot();
+
+    let settings: &'static SharedSettings = make_static!(SharedSettings::new(settings::load()));
+    log::info!("Settings: {:?}", settings.get());
+
     let peripherals = Peripherals::take();
 
     let system = peripherals.SYSTEM.split();
@@ -156,26 +185,44 @@ async fn main(spawner: Spawner) -> ! {
     )
     .unwrap();
 
-    let wifi = peripherals.WIFI;
-    let (wifi_interface, controller) =
-        esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice).unwrap();
-
     let timer_group0 = TimerGroup::new(peripherals.TIMG0, &clocks);
     embassy::init(&clocks, timer_group0.timer0);
 
-    let config = Config::dhcpv4(Default::default());
+    let wifi = peripherals.WIFI;
 
     let seed = 1234; // very random, very secure seed
 
+    let credentials = match (settings::load_credentials(), provisioning::take_request()) {
+        (Some(credentials), false) => credentials,
+        (credentials, _) => {
+            let (wifi_interface, controller) =
+                esp_wifi::wifi::new_with_mode(&init, wifi, WifiApDevice).unwrap();
+
+            let stack = make_static!(Stack::new(
+                wifi_interface,
+                provisioning::config(),
+                make_static!(StackResources::<4>::new()),
+                seed,
+            ));
+
+            provisioning::run(stack, controller, credentials.is_some()).await
+        }
+    };
+
+    let (wifi_interface, controller) =
+        esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice).unwrap();
+
+    let config = Config::dhcpv4(Default::default());
+
     // Init network stack
     let stack = make_static!(Stack::new(
         wifi_interface,
         config,
-        make_static!(StackResources::<4>::new()),
+        make_static!(StackResources::<10>::new()),
         seed,
     ));
 
-    spawner.spawn(connection(controller)).ok();
+    spawner.spawn(connection(controller, credentials)).ok();
     spawner.spawn(net_task(stack)).ok();
 
     loop {
@@ -194,6 +241,9 @@ async fn main(spawner: Spawner) -> ! {
 
     log::info!("Got IP: {}", config.address);
 
+    // Connecting is as much as an update has to manage to be kept
+    ota::confirm();
+
     {
         let rx_meta = make_static!([PacketMetadata::EMPTY; 8]);
         let rx_buffer = make_static!([0; 1500]);
@@ -203,37 +253,120 @@ async fn main(spawner: Spawner) -> ! {
         let mut mdns_socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
 
         mdns_socket
-            .bind(IpListenEndpoint { addr: None, port: MDNS_PORT })
+            .bind(IpListenEndpoint { addr: None, port: mdns::PORT })
             .unwrap();
 
-        stack.join_multicast_group(MDNS_ADDR).await.unwrap();
+        let [a, b, c, d] = mdns::ADDRESS;
+        stack
+            .join_multicast_group(Ipv4Address::new(a, b, c, d))
+            .await
+            .unwrap();
 
         spawner
-            .spawn(mdns_task(mdns_socket, config.address.address()))
+            .spawn(mdns_task(mdns_socket, stack, settings))
             .expect("spawn mdns task");
     }
 
     {
-        let rx_meta = make_static!([PacketMetadata::EMPTY; 8]);
-        let rx_buffer = make_static!([0; 1500]);
-        let tx_meta = make_static!([PacketMetadata::EMPTY; 8]);
-        let tx_buffer = make_static!([0; 1500]);
+        // Room for all fragments of a frame of the longest strip
+        let inputs = [
+            Input {
+                protocol: Protocol::Native,
+                socket: udp_socket!(stack, packet::PORT, 4, packet::MAX_PACKET_LEN),
+            },
+            Input {
+                protocol: Protocol::Ddp,
+                socket: udp_socket!(stack, ddp::PORT, 4, packet::MAX_PACKET_LEN),
+            },
+            Input {
+                protocol: Protocol::E131,
+                socket: udp_socket!(stack, e131::PORT, 4, e131::MAX_PACKET_LEN),
+            },
+            Input {
+                protocol: Protocol::ArtNet,
+                socket: udp_socket!(stack, artnet::PORT, 4, artnet::MAX_PACKET_LEN),
+            },
+            Input {
+                protocol: Protocol::Wled,
+                socket: udp_socket!(stack, wled::PORT, 4, packet::MAX_PACKET_LEN),
+            },
+        ];
+
+        // E1.31 is usually multicast, to a group per universe. Only a few groups can be joined,
+        // so long strips need the sender to unicast. Joined once, so changing the universes
+        // restarts the device.
+        let universes = settings.get().universe_map(e131::FIRST_UNIVERSE);
+        for universe in universes.universes(MAX_LEDS) {
+            let [a, b, c, d] = e131::multicast_address(universe);
+            if let Err(e) = stack
+                .join_multicast_group(Ipv4Address::new(a, b, c, d))
+                .await
+            {
+                log::warn!("Failed to join multicast group of universe {}: {:?}", universe, e);
+            }
+        }
 
-        let mut leds_socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        let frames: &'static FrameStore = make_static!(FrameStore::new());
+
+        spawn_task("Receive", move || receive_frames(inputs, frames, settings)).await;
+
+        let io = IO::new(peripherals.GPIO, peripherals.IO_MUX);
+
+        // Every pin is a different type, so the bus is created in every arm
+        macro_rules! leds_spi {
+            ($($num:literal => $gpio:ident),*) => {
+                match settings.get().data_pin {
+                    $($num => Spi::new_mosi_only(
+                        peripherals.SPI2,
+                        io.pins.$gpio,
+                        SPI_FREQUENCY_KHZ.kHz(),
+                        SpiMode::Mode0,
+                        &clocks,
+                    ),)*
+                    pin => {
+                        log::error!("GPIO{} can't drive LEDs, using GPIO7", pin);
+                        Spi::new_mosi_only(
+                            peripherals.SPI2,
+                            io.pins.gpio7,
+                            SPI_FREQUENCY_KHZ.kHz(),
+                            SpiMode::Mode0,
+                            &clocks,
+                        )
+                    }
+                }
+            };
+        }
 
-        leds_socket
-            .bind(IpListenEndpoint { addr: None, port: LEDS_PORT })
-            .unwrap();
+        // GPIO11 to GPIO17 are taken by flash and USB
+        let spi = leds_spi!(
+            0 => gpio0, 1 => gpio1, 2 => gpio2, 3 => gpio3, 4 => gpio4, 5 => gpio5, 6 => gpio6,
+            7 => gpio7, 8 => gpio8, 9 => gpio9, 10 => gpio10, 18 => gpio18, 19 => gpio19,
+            20 => gpio20, 21 => gpio21
+        );
+
+        // A frame of `MAX_LEDS` is about 6 KB, taking two descriptors of three words, as each
+        // covers up to 4092 bytes. Nothing is received, but the channel needs receive descriptors.
+        let tx_descriptors = make_static!([0u32; 2 * 3]);
+        let rx_descriptors = make_static!([0u32; 3]);
+        let dma = Gdma::new(peripherals.DMA);
+        let spi = spi.with_dma(dma.channel0.configure(
+            false,
+            tx_descriptors,
+            rx_descriptors,
+            DmaPriority::Priority0,
+        ));
+
+        spawn_task("LEDs", move || run_leds(spi, frames, settings)).await;
+    }
 
-        spawn_task("LEDs", {
-            let io = IO::new(peripherals.GPIO, peripherals.IO_MUX);
-            let rmt = Rmt::new(peripherals.RMT, 20u32.MHz(), &clocks).unwrap();
+    {
+        let socket = udp_socket!(stack, home_leds_protocol::settings::PORT, 2, MAX_MESSAGE_LEN);
 
-            || run_leds(rmt, io.pins.gpio7.into_push_pull_output(), leds_socket)
-        })
-        .await;
+        spawn_task("Settings", move || settings::run(socket, settings)).await;
     }
 
+    spawn_task("OTA", || ota::serve(stack)).await;
+
     // {
     //     let rx_buffer = make_static!([0; 1500]);
     //     let tx_buffer = make_static!([0; 1500]);
@@ -285,58 +418,95 @@ async fn main(spawner: Spawner) -> ! {
 //     }
 // }
 
+/// Answers mDNS queries for this device and its service, and announces them whenever the address
+/// or LED count changes.
 #[embassy_executor::task]
-async fn mdns_task(socket: UdpSocket<'static>, address: Ipv4Address) -> ! {
+async fn mdns_task(
+    socket: UdpSocket<'static>,
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    settings: &'static SharedSettings,
+) -> ! {
     log::debug!("Starting mDNS responder");
 
+    let [a, b, c, d] = mdns::ADDRESS;
+    let group = (Ipv4Address::new(a, b, c, d), mdns::PORT);
+
     let mut buf = [0; 1024];
+    let mut reply = [0; 1024];
+
+    // What was last announced, and how many more times to announce it
+    let mut announced = None;
+    let mut announcements = 0;
 
     loop {
-        let Ok((n, peer)) = socket.recv_from(&mut buf).await else {
+        let Some(config) = stack.config_v4() else {
+            Timer::after(MDNS_CHECK_INTERVAL).await;
             continue;
         };
 
-        log::trace!("Received {} bytes from {}", n, peer);
+        let service = mdns::Service {
+            hostname: env!("HOSTNAME"),
+            address: config.address.address().0,
+            port: packet::PORT,
+            leds: settings.get().leds,
+            firmware_version: env!("CARGO_PKG_VERSION"),
+        };
+
+        // Announced twice, as packets may be lost
+        if announced != Some((service.address, service.leds)) {
+            announced = Some((service.address, service.leds));
+            announcements = 2;
+        }
+
+        if announcements > 0 {
+            announcements -= 1;
+
+            log::debug!("Announcing mDNS records");
+
+            match mdns::announce(&service, &mut reply) {
+                Ok(len) => {
+                    if let Err(e) = socket.send_to(&reply[..len], group).await {
+                        log::warn!("Failed to send mDNS announcement: {:?}", e);
+                    }
+                }
+                Err(e) => log::error!("Failed to build mDNS announcement: {:?}", e),
+            }
+        }
 
-        let Ok(msg) = dnsparse::Message::parse(&mut buf[..n]) else {
+        let Ok(Ok((n, peer))) = with_timeout(MDNS_CHECK_INTERVAL, socket.recv_from(&mut buf)).await
+        else {
             continue;
         };
 
-        for question in msg.questions() {
-            if question.name() == MDNS_NAME && *question.kind() == QueryKind::A {
+        log::trace!("Received {} bytes from {}", n, peer);
+
+        match mdns::answer(&buf[..n], peer.port, &service, &mut reply) {
+            Ok(Some(response)) => {
                 log::debug!("Responding to mDNS query");
 
-                let mut buf = dnsparse::Message::BUFFER;
-
-                let mut msg = dnsparse::Message::builder(&mut buf)
-                    .header(
-                        dnsparse::Header::builder()
-                            .kind(HeaderKind::Response)
-                            .build(),
-                    )
-                    .build();
-
-                msg.add_answer(&Answer {
-                    name: question.name().clone(),
-                    kind: QueryKind::A,
-                    class: QueryClass::IN,
-                    ttl: 120,
-                    rdata: address.as_bytes(),
-                });
-
-                socket
-                    .send_to(&msg, (MDNS_ADDR, MDNS_PORT))
-                    .await
-                    .expect("send");
+                let result = if response.unicast {
+                    socket.send_to(&reply[..response.len], peer).await
+                } else {
+                    socket.send_to(&reply[..response.len], group).await
+                };
+
+                if let Err(e) = result {
+                    log::warn!("Failed to send mDNS response: {:?}", e);
+                }
             }
+            Ok(None) => {}
+            Err(e) => log::trace!("Ignoring mDNS message: {:?}", e),
         }
     }
 }
 
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
+async fn connection(mut controller: WifiController<'static>, credentials: Credentials) {
     log::info!("start connection task");
     log::info!("Device capabilities: {:?}", controller.get_capabilities());
+
+    let mut failed_attempts = 0;
+
     loop {
         if let WifiState::StaConnected = esp_wifi::wifi::get_wifi_state() {
             // wait until we're no longer connected
@@ -346,8 +516,8 @@ async fn connection(mut controller: WifiController<'static>) {
 
         if !matches!(controller.is_started(), Ok(true)) {
             let client_config = Configuration::Client(ClientConfiguration {
-                ssid: SSID.into(),
-                password: PASSWORD.into(),
+                ssid: credentials.ssid().into(),
+                password: credentials.password().into(),
                 ..Default::default()
             });
             controller.set_configuration(&client_config).unwrap();
@@ -358,9 +528,19 @@ async fn connection(mut controller: WifiController<'static>) {
         log::info!("About to connect...");
 
         match controller.connect().await {
-            Ok(_) => log::info!("Wifi connected!"),
+            Ok(_) => {
+                log::info!("Wifi connected!");
+                failed_attempts = 0;
+            }
             Err(e) => {
                 log::info!("Failed to connect to wifi: {:?}", e);
+
+                failed_attempts += 1;
+                if failed_attempts >= MAX_CONNECT_ATTEMPTS {
+                    log::warn!("Giving up on {}, starting provisioning", credentials.ssid());
+                    provisioning::restart_into();
+                }
+
                 Timer::after(Duration::from_millis(5000)).await
             }
         }
@@ -372,66 +552,88 @@ async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
     stack.run().await
 }
 
-async fn run_leds<P: Peripheral<P: OutputPin>>(
-    rmt: Rmt<'static>,
-    pin: P,
-    leds_socket: UdpSocket<'static>,
+/// Receives packets in any protocol, latching each completed frame into `frames`.
+async fn receive_frames<const N: usize>(
+    inputs: [Input; N],
+    frames: &FrameStore,
+    settings: &SharedSettings,
 ) -> ! {
-    let channel = rmt
-        .channel0
-        .configure(pin, TxChannelConfig {
-            clk_divider: 1,
-            idle_output_level: false,
-            idle_output: true,
-            carrier_modulation: false,
-            carrier_high: 1,
-            carrier_low: 1,
-            carrier_level: false,
-        })
-        .unwrap();
-
-    let mut ws2812 = RmtWs2812::<_, _, 100>::new(channel);
-
-    let mut ticker = Ticker::every(Duration::from_secs(1) / 60);
-
-    let mut hue = Wrapping(0u8);
-
-    // loop {
-    //     let colors = (0..100).map(|i| {
-    //         hsv::hsv2rgb(Hsv {
-    //             hue: hue.0.wrapping_add(i as u8 * 2),
-    //             sat: 255,
-    //             val: 255,
-    //         })
-    //     });
-
-    //     let colors = gamma(colors);
-
-    //     ws2812.write(colors).unwrap();
-
-    //     hue -= Wrapping(1);
-    //     ticker.next().await;
-    // }
+    let mut frame = [RGB8::default(); MAX_LEDS];
+    let mut decoder = Decoder::default();
+
+    // Number of LEDs in a frame, as far as any frame has reached
+    let mut len = 0;
 
     loop {
-        let mut buf = [0; 1024];
+        let mut buf = [0; packet::MAX_PACKET_LEN];
+
+        let (protocol, n) = input::receive(&inputs, &mut buf).await;
 
-        let Ok((n, _)) = leds_socket.recv_from(&mut buf).await else {
+        log::trace!("Received {} bytes of {:?}", n, protocol);
+
+        // Read for every packet, as the universes may be changed at runtime
+        let Some(update) = decoder.decode(protocol, &buf[..n], &settings.get()) else {
             continue;
         };
 
-        log::trace!("Received {} bytes", n);
+        for (index, [r, g, b]) in update.pixels.iter() {
+            if let Some(led) = frame.get_mut(index) {
+                *led = RGB8::new(r, g, b);
+            }
+        }
+
+        if !update.push {
+            continue;
+        }
+
+        len = len.max(update.pixels.end().min(MAX_LEDS));
+
+        // Senders may ask for a different timeout, or none at all
+        frames.push(&frame[..len], update.timeout.unwrap_or(WATCHDOG_TIMEOUT));
+    }
+}
+
+/// Writes the latest frame to the strip at a fixed rate, falling back to the local effect from the
+/// settings when frames stop arriving.
+async fn run_leds<S: SpiBus>(spi: S, frames: &FrameStore, settings: &SharedSettings) -> ! {
+    let mut ws2812 = SpiWs2812::<_, MAX_LEDS>::new(spi);
 
-        let colors = &mut buf[..n]
-            .chunks_exact(3)
-            .map(|chunk| RGB8::new(chunk[0], chunk[1], chunk[2]));
+    let mut ticker = Ticker::every(Duration::from_hz(FRAME_RATE));
 
-        let colors = gamma(colors);
+    let mut out = [RGB8::default(); MAX_LEDS];
 
-        ws2812.write(colors).unwrap();
+    // Frames of the local effect rendered, or `None` while showing received frames
+    let mut local_tick = Some(0u32);
 
-        Timer::after_millis(1).await;
+    loop {
+        let settings = settings.get();
+        let len = (settings.leds as usize).min(MAX_LEDS);
+
+        out.fill(RGB8::default());
+
+        if frames.render(Instant::now(), settings.interpolate, &mut out) {
+            if local_tick.take().is_some() {
+                log::info!("Receiving frames");
+            }
+        } else {
+            let tick = local_tick.get_or_insert_with(|| {
+                log::info!("No frames received, switching to local effect");
+                0
+            });
+
+            local::render(settings.local_effect, *tick, &mut out[..len]);
+            *tick = tick.wrapping_add(1);
+        }
+
+        let colors = gamma(out[..len].iter().copied()).map(|color| {
+            let [r, g, b] = settings.color_order.apply([color.r, color.g, color.b]);
+            RGB8::new(r, g, b)
+        });
+
+        if let Err(e) = ws2812.write(colors).await {
+            log::error!("Failed to write LEDs: {:?}", e);
+        }
 
-        // ticker.next().await;
+        ticker.next().await;
     }
 }
@@ -0,0 +1,322 @@
+//! Over-the-air updates, uploaded over HTTP:
+//!
+//! ```sh
+//! espflash save-image --chip esp32c3 target/riscv32imc-unknown-none-elf/release/home-leds home-leds.bin
+//! digest=$(sha256sum home-leds.bin | cut -d' ' -f1)
+//! mac=$(printf %s "$digest" | openssl dgst -sha256 -hmac "$UPDATE_KEY" -r | cut -d' ' -f1)
+//! curl --data-binary @home-leds.bin "http://$HOST/update?sha256=$digest&mac=$mac"
+//! ```
+//!
+//! The MAC is an HMAC-SHA256 of the digest, as hex, with the update key set during provisioning.
+//! It's checked before anything is written, so only whoever knows the key can update. Without a
+//! key, updates are refused. A captured request can be replayed to install the same image again.
+//! The key is asked for when setting up a device without credentials, and once stored it can
+//! only be replaced by giving the current one too, see [`ota::change_update_key`].
+//!
+//! The image is written to the app partition that isn't running and booted on the next start. It
+//! has to confirm itself once it's connected, or the start after that rolls back to the previous
+//! image. Rolling back is done by [`check_boot`] in the new image, as the bootloader espflash
+//! installs isn't built with rollback support, so an image that crashes before getting there
+//! keeps crashing until it's flashed over USB.
+
+use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorage;
+use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
+use home_leds_protocol::{
+    http,
+    ota::{self, Entry, ImageState, ENTRY_LEN, IMAGE_MAGIC},
+};
+use sha2::{Digest, Sha256};
+
+use crate::settings::{self, Credentials};
+
+/// Offsets and sizes of the partitions in `partitions.csv`.
+const OTADATA_OFFSET: u32 = 0xd000;
+const APP_OFFSETS: [u32; 2] = [0x10000, 0x200000];
+const APP_SIZE: usize = 0x1f0000;
+
+const SECTOR_SIZE: usize = FlashStorage::SECTOR_SIZE as usize;
+
+#[derive(Debug)]
+enum Error {
+    Flash(esp_storage::FlashStorageError),
+    Tcp(embassy_net::tcp::Error),
+    Http(http::Error),
+    /// The request isn't an upload with a valid digest and MAC.
+    BadRequest,
+    /// No update key was set during provisioning.
+    NoKey,
+    /// The MAC doesn't match the digest, so whoever sent it doesn't know the update key.
+    Unauthorized,
+    TooLarge,
+    /// The upload doesn't start like an app image.
+    NotAnImage,
+    DigestMismatch,
+    Closed,
+}
+
+fn read_entries(flash: &mut FlashStorage) -> Result<[Option<Entry>; 2], Error> {
+    let mut entries = [None; 2];
+
+    for (sector, entry) in entries.iter_mut().enumerate() {
+        let mut data = [0; ENTRY_LEN];
+        flash
+            .read(OTADATA_OFFSET + (sector * SECTOR_SIZE) as u32, &mut data)
+            .map_err(Error::Flash)?;
+
+        *entry = Entry::decode(&data);
+    }
+
+    Ok(entries)
+}
+
+fn write_entry(flash: &mut FlashStorage, sector: usize, entry: Entry) -> Result<(), Error> {
+    flash
+        .write(OTADATA_OFFSET + (sector * SECTOR_SIZE) as u32, &entry.encode())
+        .map_err(Error::Flash)
+}
+
+/// Tracks the state of the running image, rolling back if it was booted before without
+/// confirming itself.
+pub fn check_boot() {
+    let mut flash = FlashStorage::new();
+
+    let entries = match read_entries(&mut flash) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Failed to read OTA data: {:?}", e);
+            return;
+        }
+    };
+
+    // Flashed over USB, nothing to track
+    let Some(current) = ota::selected(&entries) else {
+        return;
+    };
+    let entry = entries[current].unwrap();
+
+    let result = match entry.state {
+        ImageState::New => {
+            log::info!("Booting updated firmware, waiting for it to confirm itself");
+            write_entry(&mut flash, current, Entry {
+                state: ImageState::PendingVerify,
+                ..entry
+            })
+        }
+        ImageState::PendingVerify
+            if entries[1 - current].is_some_and(|entry| entry.is_bootable()) =>
+        {
+            log::warn!("Updated firmware never confirmed itself, rolling back");
+            let result =
+                write_entry(&mut flash, current, Entry { state: ImageState::Invalid, ..entry });
+
+            if result.is_ok() {
+                esp32c3_hal::reset::software_reset();
+            }
+
+            result
+        }
+        _ => Ok(()),
+    };
+
+    if let Err(e) = result {
+        log::error!("Failed to update OTA data: {:?}", e);
+    }
+}
+
+/// Marks the running image as working, so it's booted from now on.
+pub fn confirm() {
+    let mut flash = FlashStorage::new();
+
+    let result = read_entries(&mut flash).and_then(|entries| {
+        match ota::selected(&entries).map(|current| (current, entries[current].unwrap())) {
+            Some((current, entry)) if entry.state == ImageState::PendingVerify => {
+                log::info!("Confirming updated firmware");
+                write_entry(&mut flash, current, Entry { state: ImageState::Valid, ..entry })
+            }
+            _ => Ok(()),
+        }
+    });
+
+    if let Err(e) = result {
+        log::error!("Failed to confirm firmware: {:?}", e);
+    }
+}
+
+/// Serves update uploads, restarting into the new image after one succeeds.
+pub async fn serve(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) -> ! {
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 512];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(30)));
+
+        if let Err(e) = socket.accept(http::PORT).await {
+            log::warn!("Failed to accept HTTP connection: {:?}", e);
+            continue;
+        }
+
+        let result = receive_update(&mut socket).await;
+
+        let (status, body) = match &result {
+            Ok(()) => ("200 OK", "Updated, restarting\n"),
+            Err(Error::BadRequest) => {
+                ("400 Bad Request", "Expected POST /update?sha256=...&mac=...\n")
+            }
+            Err(Error::NoKey) => ("403 Forbidden", "No update key set, provision one first\n"),
+            Err(Error::Unauthorized) => ("403 Forbidden", "MAC mismatch\n"),
+            Err(Error::TooLarge) => ("413 Content Too Large", "Image too large\n"),
+            Err(Error::NotAnImage) => ("400 Bad Request", "Not an app image\n"),
+            Err(Error::DigestMismatch) => ("400 Bad Request", "SHA-256 mismatch\n"),
+            Err(_) => ("500 Internal Server Error", "Update failed\n"),
+        };
+
+        if let Err(e) = &result {
+            log::warn!("Update failed: {:?}", e);
+        }
+
+        let mut header = [0; 128];
+        if let Ok(len) = http::response_header(status, "text/plain", body.len(), &mut header) {
+            let _ = socket.write_all(&header[..len]).await;
+            let _ = socket.write_all(body.as_bytes()).await;
+        }
+
+        socket.close();
+        let _ = socket.flush().await;
+
+        if result.is_ok() {
+            log::info!("Update written, restarting");
+
+            Timer::after_millis(500).await;
+            esp32c3_hal::reset::software_reset();
+        }
+    }
+}
+
+/// Receives an image, writes it to the inactive app partition and switches to it.
+async fn receive_update(socket: &mut TcpSocket<'_>) -> Result<(), Error> {
+    let mut buf = [0; SECTOR_SIZE];
+    let mut len = 0;
+
+    // Read the request head, along with whatever part of the body comes with it
+    let request = loop {
+        let n = socket.read(&mut buf[len..]).await.map_err(Error::Tcp)?;
+        if n == 0 {
+            return Err(Error::Closed);
+        }
+        len += n;
+
+        if let Some(request) = http::Request::parse(&buf[..len]).map_err(Error::Http)? {
+            break request;
+        }
+
+        if len == buf.len() {
+            return Err(Error::BadRequest);
+        }
+    };
+
+    let (digest_hex, mac) = request
+        .path
+        .strip_prefix("/update?sha256=")
+        .filter(|_| request.method == "POST")
+        .and_then(|query| query.split_once("&mac="))
+        .ok_or(Error::BadRequest)?;
+    let digest = ota::parse_digest(digest_hex).ok_or(Error::BadRequest)?;
+    let mac = ota::parse_digest(mac).ok_or(Error::BadRequest)?;
+
+    let credentials = settings::load_credentials();
+    let key = credentials
+        .as_ref()
+        .and_then(Credentials::update_key)
+        .ok_or(Error::NoKey)?;
+    if !verify_mac(key.as_bytes(), digest_hex.as_bytes(), &mac) {
+        return Err(Error::Unauthorized);
+    }
+
+    if request.content_length > APP_SIZE {
+        return Err(Error::TooLarge);
+    }
+
+    let mut flash = FlashStorage::new();
+    let entries = read_entries(&mut flash)?;
+    let running =
+        ota::selected(&entries).map_or(0, |current| entries[current].unwrap().partition());
+    let partition = 1 - running;
+
+    log::info!("Receiving {} byte update into partition {}", request.content_length, partition);
+
+    // Move the start of the body to the start of the buffer
+    let header_len = request.header_len;
+    let mut remaining = request.content_length;
+    buf.copy_within(header_len..len, 0);
+    len -= header_len;
+
+    if len > 0 && buf[0] != IMAGE_MAGIC {
+        return Err(Error::NotAnImage);
+    }
+
+    let mut hasher = Sha256::new();
+    let mut offset = APP_OFFSETS[partition];
+
+    loop {
+        // Fill a whole sector, unless the image ends first
+        while len < buf.len() && len < remaining {
+            let n = socket.read(&mut buf[len..]).await.map_err(Error::Tcp)?;
+            if n == 0 {
+                return Err(Error::Closed);
+            }
+            len += n;
+        }
+
+        let chunk = &buf[..len.min(remaining)];
+        if offset == APP_OFFSETS[partition] && chunk.first() != Some(&IMAGE_MAGIC) {
+            return Err(Error::NotAnImage);
+        }
+
+        hasher.update(chunk);
+        flash.write(offset, chunk).map_err(Error::Flash)?;
+
+        offset += chunk.len() as u32;
+        remaining -= chunk.len();
+        len = 0;
+
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    if hasher.finalize()[..] != digest[..] {
+        return Err(Error::DigestMismatch);
+    }
+
+    let (sector, entry) = ota::next(&entries, partition);
+    write_entry(&mut flash, sector, entry)
+}
+
+/// Checks an HMAC-SHA256 (RFC 2104) of `message`, taking as long no matter which bytes differ.
+fn verify_mac(key: &[u8], message: &[u8], mac: &[u8; 32]) -> bool {
+    const BLOCK_LEN: usize = 64;
+
+    let mut block = [0; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize();
+
+    outer.iter().zip(mac).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,339 @@
+use embassy_net::{
+    tcp::TcpSocket,
+    udp::{PacketMetadata, UdpSocket},
+    Config, IpListenEndpoint, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4,
+};
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_io_async::Write;
+use embedded_svc::wifi::{AccessPointConfiguration, Configuration, Wifi};
+use esp32c3_hal::macros::ram;
+use esp_wifi::wifi::{WifiApDevice, WifiController, WifiDevice};
+use home_leds_protocol::{dhcp, dns, http, ota};
+
+use crate::{
+    settings::{self, Credentials},
+    spawn_task,
+};
+
+const AP_SSID: &str = concat!(env!("HOSTNAME"), "-setup");
+const AP_ADDRESS: [u8; 4] = [192, 168, 4, 1];
+
+/// How long to wait for new credentials before trying the stored ones again.
+const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Kept in RTC memory across a restart, to start in provisioning mode after failing to connect.
+#[ram(rtc_fast, uninitialized)]
+static mut REQUESTED: u32 = 0;
+
+const REQUESTED_MAGIC: u32 = 0x5052_4f56;
+
+/// The form, in parts so the update key fields can be left out.
+const FORM_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Home LEDs setup</title>
+</head>
+<body>
+<h1>Home LEDs setup</h1>
+<form method="post" action="/save">
+<p><label>Network <input name="ssid" maxlength="32" required></label></p>
+<p><label>Password <input name="password" type="password" maxlength="64"></label></p>
+"#;
+
+const FORM_UPDATE_KEY: &str = r#"<p><label>Update key <input name="update_key" type="password" maxlength="64"></label></p>
+<p><small>Firmware updates have to be signed with this key. Without one, updates are refused.
+</small></p>
+"#;
+
+const FORM_TAIL: &str = r#"<p><button>Connect</button></p>
+</form>
+</body>
+</html>
+"#;
+
+const SAVED_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Home LEDs setup</title>
+</head>
+<body>
+<h1>Saved</h1>
+<p>Restarting to connect to the network.</p>
+</body>
+</html>
+"#;
+
+/// Whether provisioning mode was requested before the last restart. Clears the request.
+pub fn take_request() -> bool {
+    unsafe {
+        let requested = REQUESTED == REQUESTED_MAGIC;
+        REQUESTED = 0;
+        requested
+    }
+}
+
+/// Restarts into provisioning mode.
+pub fn restart_into() -> ! {
+    unsafe {
+        REQUESTED = REQUESTED_MAGIC;
+    }
+
+    restart()
+}
+
+fn restart() -> ! {
+    esp32c3_hal::reset::software_reset();
+
+    loop {}
+}
+
+pub fn config() -> Config {
+    let [a, b, c, d] = AP_ADDRESS;
+
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::new(a, b, c, d), 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+/// Runs an access point with a captive portal asking for Wi-Fi credentials, then restarts to
+/// connect with them.
+///
+/// With `retry`, gives up after a while and restarts to try the stored credentials again. The
+/// access point is open and started without anyone asking for it, so then the update key isn't
+/// offered, and can only be replaced along with the current one.
+pub async fn run(
+    stack: &'static Stack<WifiDevice<'static, WifiApDevice>>,
+    mut controller: WifiController<'static>,
+    retry: bool,
+) -> ! {
+    log::info!("Starting provisioning access point {}", AP_SSID);
+
+    controller
+        .set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+            ssid: AP_SSID.into(),
+            ..Default::default()
+        }))
+        .unwrap();
+    controller.start().await.unwrap();
+
+    spawn_task("Provisioning network", || stack.run()).await;
+    spawn_task("DHCP", || dhcp_server(stack)).await;
+    spawn_task("DNS", || dns_server(stack)).await;
+
+    if retry {
+        if with_timeout(TIMEOUT, http_server(stack, false))
+            .await
+            .is_err()
+        {
+            log::info!("No credentials received, trying the stored ones again");
+        }
+
+        restart()
+    } else {
+        http_server(stack, true).await
+    }
+}
+
+async fn dhcp_server(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+
+    let mut socket =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket
+        .bind(IpListenEndpoint { addr: None, port: dhcp::SERVER_PORT })
+        .unwrap();
+
+    let mut server = dhcp::Server::new(AP_ADDRESS);
+    let mut buf = [0; 576];
+    let mut reply = [0; 576];
+
+    loop {
+        let Ok((n, _)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+
+        match server.handle(&buf[..n], &mut reply) {
+            Ok(len) => {
+                // Clients don't have an address to reply to yet
+                let broadcast = (Ipv4Address::BROADCAST, dhcp::CLIENT_PORT);
+                if let Err(e) = socket.send_to(&reply[..len], broadcast).await {
+                    log::warn!("Failed to send DHCP reply: {:?}", e);
+                }
+            }
+            Err(e) => log::debug!("Ignoring DHCP message: {:?}", e),
+        }
+    }
+}
+
+async fn dns_server(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+
+    let mut socket =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket
+        .bind(IpListenEndpoint { addr: None, port: dns::PORT })
+        .unwrap();
+
+    let mut buf = [0; 512];
+    let mut reply = [0; 512];
+
+    loop {
+        let Ok((n, peer)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+
+        match dns::answer_all(&buf[..n], AP_ADDRESS, &mut reply) {
+            Ok(len) => {
+                if let Err(e) = socket.send_to(&reply[..len], peer).await {
+                    log::warn!("Failed to send DNS reply: {:?}", e);
+                }
+            }
+            Err(e) => log::debug!("Ignoring DNS message: {:?}", e),
+        }
+    }
+}
+
+/// Serves the form on every path, which makes phones show it as a captive portal, until
+/// credentials are submitted. With `offer_update_key`, the form asks for the update key too.
+async fn http_server(
+    stack: &'static Stack<WifiDevice<'static, WifiApDevice>>,
+    offer_update_key: bool,
+) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 2048];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        if let Err(e) = socket.accept(http::PORT).await {
+            log::warn!("Failed to accept HTTP connection: {:?}", e);
+            continue;
+        }
+
+        let credentials = match handle_request(&mut socket, offer_update_key).await {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                log::warn!("Failed to handle HTTP request: {:?}", e);
+                None
+            }
+        };
+
+        socket.close();
+        let _ = socket.flush().await;
+
+        if let Some(credentials) = credentials {
+            log::info!("Received credentials for {}, restarting", credentials.ssid());
+
+            settings::save_credentials(&credentials);
+
+            Timer::after_millis(500).await;
+            restart();
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Error {
+    Tcp(embassy_net::tcp::Error),
+    Http(http::Error),
+    /// The request doesn't fit in the buffer.
+    TooLong,
+    Closed,
+}
+
+/// Responds to a single request, returning the credentials if they were submitted.
+async fn handle_request(
+    socket: &mut TcpSocket<'_>,
+    offer_update_key: bool,
+) -> Result<Option<Credentials>, Error> {
+    // Room for headers and every field, percent-encoded
+    let mut buf = [0; 1536];
+    let mut len = 0;
+
+    let request = loop {
+        let n = socket.read(&mut buf[len..]).await.map_err(Error::Tcp)?;
+        if n == 0 {
+            return Err(Error::Closed);
+        }
+        len += n;
+
+        if let Some(request) = http::Request::parse(&buf[..len]).map_err(Error::Http)? {
+            let end = request.header_len + request.content_length;
+            if end > buf.len() {
+                return Err(Error::TooLong);
+            }
+
+            if len >= end {
+                break request;
+            }
+        } else if len == buf.len() {
+            return Err(Error::TooLong);
+        }
+    };
+
+    log::debug!("{} {}", request.method, request.path);
+
+    let body = &buf[request.header_len..request.header_len + request.content_length];
+    let credentials = if request.method == "POST" && request.path == "/save" {
+        let mut ssid = [0; 32];
+        let mut password = [0; 64];
+        let mut current_key = [0; settings::MAX_UPDATE_KEY_LEN];
+        let mut new_key = [0; settings::MAX_UPDATE_KEY_LEN];
+
+        let stored = settings::load_credentials();
+        let stored_key = stored
+            .as_ref()
+            .and_then(Credentials::update_key)
+            .unwrap_or_default();
+        let current_key =
+            http::form_value(body, "current_update_key", &mut current_key).unwrap_or_default();
+        let new_key = http::form_value(body, "update_key", &mut new_key).unwrap_or_default();
+
+        match ota::change_update_key(stored_key, current_key, new_key) {
+            Some(update_key) => http::form_value(body, "ssid", &mut ssid)
+                .zip(http::form_value(body, "password", &mut password))
+                .and_then(|(ssid, password)| Credentials::new(ssid, password, update_key)),
+            None => {
+                log::warn!("Refusing to replace the update key without the current one");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let parts: &[&str] = match (&credentials, offer_update_key) {
+        (Some(_), _) => &[SAVED_PAGE],
+        (None, true) => &[FORM_HEAD, FORM_UPDATE_KEY, FORM_TAIL],
+        (None, false) => &[FORM_HEAD, FORM_TAIL],
+    };
+    let len = parts.iter().map(|part| part.len()).sum();
+
+    let mut header = [0; 128];
+    let header_len =
+        http::response_header("200 OK", "text/html", len, &mut header).map_err(Error::Http)?;
+
+    socket
+        .write_all(&header[..header_len])
+        .await
+        .map_err(Error::Tcp)?;
+    for part in parts {
+        socket
+            .write_all(part.as_bytes())
+            .await
+            .map_err(Error::Tcp)?;
+    }
+
+    Ok(credentials)
+}
@@ -0,0 +1,251 @@
+use core::cell::Cell;
+
+use embassy_net::udp::UdpSocket;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Timer;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorage;
+use home_leds_protocol::{
+    e131,
+    packet::{MAGIC, VERSION},
+    settings::{Message, Settings, MAX_MESSAGE_LEN},
+};
+
+use crate::MAX_LEDS;
+
+/// Where settings are stored: the `nvs` partition in `partitions.csv`, which is otherwise unused.
+const FLASH_OFFSET: u32 = 0x9000;
+
+/// Magic, version and length of the stored records.
+const FLASH_HEADER_LEN: usize = 4;
+
+/// Where Wi-Fi credentials are stored, in the sector after the settings.
+const CREDENTIALS_OFFSET: u32 = FLASH_OFFSET + FlashStorage::SECTOR_SIZE;
+
+/// Magic, version and the lengths of the SSID and password.
+const CREDENTIALS_HEADER_LEN: usize = 5;
+const MAX_SSID_LEN: usize = 32;
+const MAX_PASSWORD_LEN: usize = 64;
+/// Stored after the password, with its length in front, so credentials saved before it existed
+/// still load. Those have an erased length of `0xff`, which reads as no key.
+pub const MAX_UPDATE_KEY_LEN: usize = 64;
+const CREDENTIALS_LEN: usize =
+    CREDENTIALS_HEADER_LEN + MAX_SSID_LEN + MAX_PASSWORD_LEN + 1 + MAX_UPDATE_KEY_LEN;
+
+/// Credentials of the Wi-Fi network to connect to, and the key updates have to be signed with.
+#[derive(Clone)]
+pub struct Credentials {
+    ssid: [u8; MAX_SSID_LEN],
+    ssid_len: usize,
+    password: [u8; MAX_PASSWORD_LEN],
+    password_len: usize,
+    update_key: [u8; MAX_UPDATE_KEY_LEN],
+    update_key_len: usize,
+}
+
+impl Credentials {
+    /// Returns `None` if the SSID or password is too long for Wi-Fi, or the update key too long
+    /// to store. An empty update key disables updates.
+    pub fn new(ssid: &str, password: &str, update_key: &str) -> Option<Self> {
+        if ssid.is_empty()
+            || ssid.len() > MAX_SSID_LEN
+            || password.len() > MAX_PASSWORD_LEN
+            || update_key.len() > MAX_UPDATE_KEY_LEN
+        {
+            return None;
+        }
+
+        let mut credentials = Credentials {
+            ssid: [0; MAX_SSID_LEN],
+            ssid_len: ssid.len(),
+            password: [0; MAX_PASSWORD_LEN],
+            password_len: password.len(),
+            update_key: [0; MAX_UPDATE_KEY_LEN],
+            update_key_len: update_key.len(),
+        };
+        credentials.ssid[..ssid.len()].copy_from_slice(ssid.as_bytes());
+        credentials.password[..password.len()].copy_from_slice(password.as_bytes());
+        credentials.update_key[..update_key.len()].copy_from_slice(update_key.as_bytes());
+
+        Some(credentials)
+    }
+
+    pub fn ssid(&self) -> &str {
+        core::str::from_utf8(&self.ssid[..self.ssid_len]).unwrap_or_default()
+    }
+
+    pub fn password(&self) -> &str {
+        core::str::from_utf8(&self.password[..self.password_len]).unwrap_or_default()
+    }
+
+    pub fn update_key(&self) -> Option<&str> {
+        let key = core::str::from_utf8(&self.update_key[..self.update_key_len]).ok()?;
+        (!key.is_empty()).then_some(key)
+    }
+}
+
+/// Settings shared between the tasks that use them, changed at runtime over the network.
+pub struct SharedSettings {
+    settings: Mutex<CriticalSectionRawMutex, Cell<Settings>>,
+}
+
+impl SharedSettings {
+    pub const fn new(settings: Settings) -> Self {
+        SharedSettings {
+            settings: Mutex::new(Cell::new(settings)),
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock(Cell::get)
+    }
+
+    fn set(&self, settings: Settings) {
+        self.settings.lock(|cell| cell.set(settings));
+    }
+}
+
+/// Reads settings from flash, falling back to defaults if none were saved yet.
+pub fn load() -> Settings {
+    let mut buf = [0; FLASH_HEADER_LEN + MAX_MESSAGE_LEN];
+
+    if let Err(e) = FlashStorage::new().read(FLASH_OFFSET, &mut buf) {
+        log::warn!("Failed to read settings: {:?}", e);
+        return Settings::default();
+    }
+
+    let [m0, m1, version, len, ref records @ ..] = buf;
+    if [m0, m1] != MAGIC || version != VERSION {
+        log::info!("No settings saved, using defaults");
+        return Settings::default();
+    }
+
+    records
+        .get(..len as usize)
+        .and_then(|records| Settings::decode(records).ok())
+        .unwrap_or_else(|| {
+            log::warn!("Saved settings are invalid, using defaults");
+            Settings::default()
+        })
+}
+
+fn save(settings: &Settings) {
+    let mut buf = [0; FLASH_HEADER_LEN + MAX_MESSAGE_LEN];
+
+    let len = settings
+        .encode(&mut buf[FLASH_HEADER_LEN..])
+        .expect("buffer fits settings");
+
+    buf[0..2].copy_from_slice(&MAGIC);
+    buf[2] = VERSION;
+    buf[3] = len as u8;
+
+    if let Err(e) = FlashStorage::new().write(FLASH_OFFSET, &buf[..FLASH_HEADER_LEN + len]) {
+        log::error!("Failed to save settings: {:?}", e);
+    }
+}
+
+/// Reads the Wi-Fi credentials from flash, if any were saved.
+pub fn load_credentials() -> Option<Credentials> {
+    let mut buf = [0; CREDENTIALS_LEN];
+
+    if let Err(e) = FlashStorage::new().read(CREDENTIALS_OFFSET, &mut buf) {
+        log::warn!("Failed to read Wi-Fi credentials: {:?}", e);
+        return None;
+    }
+
+    let [m0, m1, version, ssid_len, ref rest @ ..] = buf;
+    if [m0, m1] != MAGIC || version != VERSION {
+        return None;
+    }
+
+    let password_len = rest[0] as usize;
+    let ssid = rest[1..].get(..ssid_len as usize)?;
+    let password = rest[1 + MAX_SSID_LEN..].get(..password_len)?;
+    let (&update_key_len, update_key) =
+        rest[1 + MAX_SSID_LEN + MAX_PASSWORD_LEN..].split_first()?;
+    let update_key = update_key
+        .get(..update_key_len as usize)
+        .unwrap_or_default();
+
+    Credentials::new(
+        core::str::from_utf8(ssid).ok()?,
+        core::str::from_utf8(password).ok()?,
+        core::str::from_utf8(update_key).unwrap_or_default(),
+    )
+}
+
+pub fn save_credentials(credentials: &Credentials) {
+    let mut buf = [0; CREDENTIALS_LEN];
+
+    buf[0..2].copy_from_slice(&MAGIC);
+    buf[2] = VERSION;
+    buf[3] = credentials.ssid_len as u8;
+    buf[4] = credentials.password_len as u8;
+    buf[5..5 + MAX_SSID_LEN].copy_from_slice(&credentials.ssid);
+    buf[5 + MAX_SSID_LEN..5 + MAX_SSID_LEN + MAX_PASSWORD_LEN]
+        .copy_from_slice(&credentials.password);
+    buf[5 + MAX_SSID_LEN + MAX_PASSWORD_LEN] = credentials.update_key_len as u8;
+    buf[6 + MAX_SSID_LEN + MAX_PASSWORD_LEN..].copy_from_slice(&credentials.update_key);
+
+    if let Err(e) = FlashStorage::new().write(CREDENTIALS_OFFSET, &buf) {
+        log::error!("Failed to save Wi-Fi credentials: {:?}", e);
+    }
+}
+
+/// Answers settings messages, saving and applying new settings. Changing the data pin or the
+/// universes restarts the device, everything else applies right away. Strips longer than
+/// `MAX_LEDS` are rejected, leaving the settings as they were.
+pub async fn run(socket: UdpSocket<'static>, shared: &SharedSettings) -> ! {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+
+    loop {
+        let Ok((n, peer)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+
+        let (current, restart) = match Message::decode(&buf[..n]) {
+            Ok(Message::Get) => (shared.get(), false),
+            // Replying with the unchanged settings tells the sender they weren't taken
+            Ok(Message::Set(settings)) if settings.leds as usize > MAX_LEDS => {
+                log::warn!("Rejecting {} LEDs, at most {} can be driven", settings.leds, MAX_LEDS);
+                (shared.get(), false)
+            }
+            Ok(Message::Set(settings)) => {
+                log::info!("New settings: {:?}", settings);
+
+                let current = shared.get();
+                let restart = settings.data_pin != current.data_pin
+                    || settings.universe_map(e131::FIRST_UNIVERSE)
+                        != current.universe_map(e131::FIRST_UNIVERSE);
+
+                save(&settings);
+                shared.set(settings);
+
+                (settings, restart)
+            }
+            Ok(Message::Current(_)) => continue,
+            Err(e) => {
+                log::warn!("Invalid settings message: {:?}", e);
+                continue;
+            }
+        };
+
+        let len = Message::Current(current)
+            .encode(&mut buf)
+            .expect("buffer fits settings");
+
+        if let Err(e) = socket.send_to(&buf[..len], peer).await {
+            log::warn!("Failed to reply with settings: {:?}", e);
+        }
+
+        // Only after replying, so the sender knows the settings were saved
+        if restart {
+            log::info!("Data pin or universes changed, restarting");
+
+            // Give the reply and the log a moment to get out
+            Timer::after_millis(100).await;
+            esp32c3_hal::reset::software_reset();
+        }
+    }
+}
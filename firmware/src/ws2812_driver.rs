@@ -1,93 +1,68 @@
-use esp32c3_hal::rmt::{self, PulseCode, TxChannel};
-use smart_leds::{SmartLedsWrite, RGB8};
+use embedded_hal_async::spi::SpiBus;
+use smart_leds::RGB8;
 
-pub struct RmtWs2812<RMT: TxChannel<N>, const N: u8, const LEDS: usize>
+/// SPI clock to send WS2812 bits at, as four SPI bits of 312.5 ns each.
+pub const SPI_FREQUENCY_KHZ: u32 = 3200;
+
+/// SPI bytes sending one LED: 24 bits of four SPI bits each.
+const LED_LEN: usize = 24 * 4 / 8;
+
+/// Low SPI bytes after every frame, about 300 µs, for the strip to latch it.
+const RESET_LEN: usize = 120;
+
+/// Drives a WS2812 strip from the MOSI pin of an SPI bus, with each bit shaped as four SPI bits.
+///
+/// The frame is sent by DMA, so unlike bit-banging or refilling the RMT channel from software, no
+/// interrupts have to be masked while it's sent and Wi-Fi keeps working however long the strip.
+pub struct SpiWs2812<S: SpiBus, const LEDS: usize>
 where
-    [(); LEDS * 3 * 8 + 1]:,
+    [(); LEDS * LED_LEN + RESET_LEN]:,
 {
-    rmt: Option<RMT>,
-    buffer: [u32; LEDS * 3 * 8 + 1],
+    spi: S,
+    buffer: [u8; LEDS * LED_LEN + RESET_LEN],
 }
 
-impl<RMT: TxChannel<N>, const N: u8, const LEDS: usize> RmtWs2812<RMT, N, LEDS>
+impl<S: SpiBus, const LEDS: usize> SpiWs2812<S, LEDS>
 where
-    [(); LEDS * 3 * 8 + 1]:,
+    [(); LEDS * LED_LEN + RESET_LEN]:,
 {
-    pub fn new(rmt: RMT) -> Self {
-        Self { rmt: Some(rmt), buffer: [0; _] }
+    pub fn new(spi: S) -> Self {
+        Self { spi, buffer: [0; _] }
     }
-}
 
-impl<RMT: TxChannel<N>, const N: u8, const LEDS: usize> SmartLedsWrite for RmtWs2812<RMT, N, LEDS>
-where
-    [(); LEDS * 3 * 8 + 1]:,
-{
-    type Error = rmt::Error;
-    type Color = RGB8;
+    /// Sends a frame, at most `LEDS` long. The strip may be longer, the LEDs past the frame keep
+    /// their colour.
+    pub async fn write(&mut self, colors: impl Iterator<Item = RGB8>) -> Result<(), S::Error> {
+        let mut len = 0;
 
-    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
-    where
-        T: Iterator<Item = I>,
-        I: Into<Self::Color>,
-    {
-        iterator
-            .flat_map(|color| {
-                let color: RGB8 = color.into();
-                [color.r, color.g, color.b]
-            })
-            .flat_map(|byte| {
-                [
-                    byte & 0b1000_0000 != 0,
-                    byte & 0b0100_0000 != 0,
-                    byte & 0b0010_0000 != 0,
-                    byte & 0b0001_0000 != 0,
-                    byte & 0b0000_1000 != 0,
-                    byte & 0b0000_0100 != 0,
-                    byte & 0b0000_0010 != 0,
-                    byte & 0b0000_0001 != 0,
-                ]
-            })
-            .map(|bit| {
-                if bit {
-                    PulseCode {
-                        level1: true,
-                        length1: 15,
-                        level2: false,
-                        length2: 6,
-                    }
-                } else {
-                    PulseCode {
-                        level1: true,
-                        length1: 6,
-                        level2: false,
-                        length2: 15,
-                    }
+        for (color, out) in colors.zip(self.buffer[..LEDS * LED_LEN].chunks_exact_mut(LED_LEN)) {
+            for (byte, out) in [color.r, color.g, color.b]
+                .into_iter()
+                .zip(out.chunks_exact_mut(4))
+            {
+                // Two bits per SPI byte, most significant first
+                for (i, out) in out.iter_mut().enumerate() {
+                    let bits = byte >> (6 - 2 * i);
+                    *out = pulse(bits & 0b10 != 0) << 4 | pulse(bits & 0b01 != 0);
                 }
-            })
-            .chain(core::iter::once(PulseCode {
-                level1: true,
-                length1: 8,
-                level2: false,
-                length2: 0,
-            }))
-            .map(Into::into)
-            .zip(self.buffer.iter_mut())
-            .for_each(|(pulse, buffer)| *buffer = pulse);
-
-        // self.buffer.last_mut().unwrap().length2 = 0;
+            }
 
-        let rmt = self.rmt.take().unwrap();
+            len += LED_LEN;
+        }
 
-        let result = critical_section::with(|_| rmt.transmit(&self.buffer).wait());
+        // Leaves the line low between frames
+        self.buffer[len..len + RESET_LEN].fill(0);
 
-        match result {
-            Ok(channel) => self.rmt = Some(channel),
-            Err((e, channel)) => {
-                log::error!("Error: {:?}", e);
-                self.rmt = Some(channel)
-            }
-        };
+        self.spi.write(&self.buffer[..len + RESET_LEN]).await?;
+        self.spi.flush().await
+    }
+}
 
-        Ok(())
+/// Four SPI bits sending one WS2812 bit: high for 312.5 ns for a zero, or 937.5 ns for a one.
+fn pulse(bit: bool) -> u8 {
+    if bit {
+        0b1110
+    } else {
+        0b1000
     }
 }
@@ -13,7 +13,7 @@
       in
       {
         defaultPackage = naersk-lib.buildPackage {
-          src = ./control;
+          src = ./.;
           buildInputs = with pkgs; [ pkg-config openssl ];
         };
         devShell = with pkgs; mkShell {
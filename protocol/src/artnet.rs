@@ -0,0 +1,95 @@
+//! Art-Net `ArtDmx` packets, as sent by lighting consoles.
+//!
+//! Other Art-Net packets, like `ArtPoll`, are rejected with [`Error::UnsupportedOpCode`].
+
+/// UDP port Art-Net is sent to.
+pub const PORT: u16 = 6454;
+
+/// Art-Net counts universes from 0.
+pub const FIRST_UNIVERSE: u16 = 0;
+
+const HEADER_LEN: usize = 18;
+
+/// Length of a packet carrying a full universe.
+pub const MAX_PACKET_LEN: usize = HEADER_LEN + 512;
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooShort,
+    /// The packet isn't an Art-Net packet at all.
+    NotArtNet,
+    UnsupportedOpCode(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    /// The 15 bit port address, combining net, sub-net and universe.
+    pub universe: u16,
+    pub sequence: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn decode(packet: &'a [u8]) -> Result<Self, Error> {
+        if packet.len() < 10 {
+            return Err(Error::TooShort);
+        }
+
+        if &packet[0..8] != ID {
+            return Err(Error::NotArtNet);
+        }
+
+        // The only little endian field in the protocol
+        let op_code = u16::from_le_bytes([packet[8], packet[9]]);
+        if op_code != OP_DMX {
+            return Err(Error::UnsupportedOpCode(op_code));
+        }
+
+        if packet.len() < HEADER_LEN {
+            return Err(Error::TooShort);
+        }
+
+        let len = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+        let data = packet
+            .get(HEADER_LEN..HEADER_LEN + len)
+            .ok_or(Error::TooShort)?;
+
+        Ok(Packet {
+            universe: u16::from_be_bytes([packet[15] & 0x7f, packet[14]]),
+            sequence: packet[12],
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_dmx_packet() {
+        let packet = b"Art-Net\0\x00\x50\x00\x0e\x05\x00\x13\x02\x00\x06\x01\x02\x03\x04\x05\x06";
+
+        assert_eq!(
+            Packet::decode(packet),
+            Ok(Packet {
+                universe: 0x0213,
+                sequence: 5,
+                data: &[1, 2, 3, 4, 5, 6],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let poll = b"Art-Net\0\x00\x20\x00\x0e\x00\x00";
+        assert_eq!(Packet::decode(poll), Err(Error::UnsupportedOpCode(0x2000)));
+
+        let truncated = b"Art-Net\0\x00\x50\x00\x0e\x05\x00\x13\x02\x00\x06\x01\x02";
+        assert_eq!(Packet::decode(truncated), Err(Error::TooShort));
+
+        assert_eq!(Packet::decode(b"ArtNet\0\0\x00\x50"), Err(Error::NotArtNet));
+    }
+}
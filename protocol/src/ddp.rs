@@ -0,0 +1,256 @@
+//! The Distributed Display Protocol, as spoken by xLights, WLED and LedFx.
+//!
+//! ```text
+//! 0       1          2           3             4                 8             10
+//! +-------+----------+-----------+-------------+-----------------+-------------+--------------
+//! | flags | sequence | data type | destination | data offset (BE)| length (BE) | [timecode] data
+//! +-------+----------+-----------+-------------+-----------------+-------------+--------------
+//! ```
+//!
+//! Unlike native packets, the offset is in bytes rather than pixels. Every packet with
+//! [`FLAG_PUSH`] set ends a frame. See <http://www.3waylabs.com/ddp/> for the full spec.
+
+use crate::PixelFormat;
+
+/// UDP port DDP is sent to.
+pub const PORT: u16 = 4048;
+
+pub const HEADER_LEN: usize = 10;
+const TIMECODE_LEN: usize = 4;
+
+/// Most pixel data senders put in a packet, a whole number of both RGB and RGBW pixels.
+pub const MAX_DATA_LEN: usize = 1440;
+
+const VERSION_MASK: u8 = 0b1100_0000;
+const VERSION_1: u8 = 0b0100_0000;
+
+pub const FLAG_TIMECODE: u8 = 1 << 4;
+pub const FLAG_STORAGE: u8 = 1 << 3;
+pub const FLAG_REPLY: u8 = 1 << 2;
+pub const FLAG_QUERY: u8 = 1 << 1;
+/// Set on the last packet of a frame, telling the receiver to show it.
+pub const FLAG_PUSH: u8 = 1 << 0;
+
+/// Destination id of the default output device, i.e. the LEDs.
+pub const ID_DISPLAY: u8 = 1;
+/// Destination id addressing all devices.
+pub const ID_ALL: u8 = 255;
+
+const TYPE_RGB8: u8 = 0b00_001_011;
+const TYPE_RGBW8: u8 = 0b00_011_011;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The packet is shorter than its header says it is.
+    TooShort,
+    UnsupportedVersion(u8),
+    UnsupportedDataType(u8),
+    /// The output buffer can't hold the encoded packet.
+    BufferTooSmall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Combination of the `FLAG_*` constants.
+    pub flags: u8,
+    /// Sequence number from 1 to 15, or 0 if the sender doesn't use them.
+    pub sequence: u8,
+    pub format: PixelFormat,
+    /// One of the `ID_*` constants, or another device id.
+    pub destination: u8,
+    /// Offset of the data in bytes.
+    pub offset: u32,
+}
+
+impl Header {
+    /// Whether this packet ends a frame.
+    pub fn push(&self) -> bool {
+        self.flags & FLAG_PUSH != 0
+    }
+
+    /// Whether the data is meant for the LEDs, rather than being a query or config.
+    pub fn is_display(&self) -> bool {
+        matches!(self.destination, ID_DISPLAY | ID_ALL) && self.flags & FLAG_QUERY == 0
+    }
+
+    /// Writes the header followed by `data` into `buf`, returning the length of the packet.
+    ///
+    /// The timecode flag is ignored, packets are always sent without one.
+    pub fn encode(&self, data: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        let len = HEADER_LEN + data.len();
+        let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+        let data_len = u16::try_from(data.len()).map_err(|_| Error::BufferTooSmall)?;
+
+        buf[0] = VERSION_1 | (self.flags & !(VERSION_MASK | FLAG_TIMECODE));
+        buf[1] = self.sequence & 0x0f;
+        buf[2] = match self.format {
+            PixelFormat::Rgb8 => TYPE_RGB8,
+            PixelFormat::Rgbw8 => TYPE_RGBW8,
+        };
+        buf[3] = self.destination;
+        buf[4..8].copy_from_slice(&self.offset.to_be_bytes());
+        buf[8..10].copy_from_slice(&data_len.to_be_bytes());
+        buf[HEADER_LEN..].copy_from_slice(data);
+
+        Ok(len)
+    }
+
+    /// Parses a packet into its header and data.
+    pub fn decode(packet: &[u8]) -> Result<(Header, &[u8]), Error> {
+        if packet.len() < HEADER_LEN {
+            return Err(Error::TooShort);
+        }
+
+        if packet[0] & VERSION_MASK != VERSION_1 {
+            return Err(Error::UnsupportedVersion(packet[0] >> 6));
+        }
+
+        let format = match packet[2] {
+            TYPE_RGB8 => PixelFormat::Rgb8,
+            TYPE_RGBW8 => PixelFormat::Rgbw8,
+            // Undefined, which xLights and LedFx send for RGB
+            0x00 | 0x01 => PixelFormat::Rgb8,
+            other => return Err(Error::UnsupportedDataType(other)),
+        };
+
+        let header = Header {
+            flags: packet[0] & !VERSION_MASK,
+            sequence: packet[1] & 0x0f,
+            format,
+            destination: packet[3],
+            offset: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+        };
+
+        let data_len = u16::from_be_bytes([packet[8], packet[9]]) as usize;
+        let data_start = if header.flags & FLAG_TIMECODE != 0 {
+            HEADER_LEN + TIMECODE_LEN
+        } else {
+            HEADER_LEN
+        };
+
+        let data = packet
+            .get(data_start..data_start + data_len)
+            .ok_or(Error::TooShort)?;
+
+        Ok((header, data))
+    }
+}
+
+/// Splits a frame of pixel data into packets of at most [`MAX_DATA_LEN`] bytes.
+///
+/// Yields the byte offset and data of every packet, along with whether it ends the frame.
+pub fn fragments(data: &[u8]) -> impl Iterator<Item = (u32, &[u8], bool)> {
+    let count = data.len().div_ceil(MAX_DATA_LEN).max(1);
+
+    (0..count).map(move |index| {
+        let start = index * MAX_DATA_LEN;
+        let end = (start + MAX_DATA_LEN).min(data.len());
+
+        (start as u32, &data[start..end], index == count - 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn decodes_ledfx_packet() {
+        let packet = b"\x41\x05\x01\x01\x00\x00\x00\x00\x00\x06\xff\x00\x00\x00\xff\x00";
+        let (header, data) = Header::decode(packet).unwrap();
+
+        assert_eq!(
+            header,
+            Header {
+                flags: FLAG_PUSH,
+                sequence: 5,
+                format: PixelFormat::Rgb8,
+                destination: ID_DISPLAY,
+                offset: 0,
+            }
+        );
+        assert!(header.push());
+        assert!(header.is_display());
+        assert_eq!(data, b"\xff\x00\x00\x00\xff\x00");
+    }
+
+    #[test]
+    fn decodes_xlights_fragment() {
+        let mut packet = Vec::from(*b"\x40\x03\x0b\x01\x00\x00\x05\xa0\x05\xa0");
+        packet.extend((0..1440).map(|i| i as u8));
+        let (header, data) = Header::decode(&packet).unwrap();
+
+        assert!(!header.push());
+        assert_eq!(header.sequence, 3);
+        assert_eq!(header.offset, 1440);
+        assert_eq!(data, &packet[HEADER_LEN..]);
+    }
+
+    #[test]
+    fn decodes_rgbw_packet_with_timecode() {
+        let packet = b"\x51\x0f\x1b\x01\x00\x00\x00\x08\x00\x04\x12\x34\x56\x78\x01\x02\x03\x04";
+        let (header, data) = Header::decode(packet).unwrap();
+
+        assert_eq!(header.flags, FLAG_TIMECODE | FLAG_PUSH);
+        assert_eq!(header.format, PixelFormat::Rgbw8);
+        assert_eq!(header.offset, 8);
+        assert_eq!(data, b"\x01\x02\x03\x04");
+    }
+
+    #[test]
+    fn decodes_status_query() {
+        let packet = b"\x42\x00\x00\xfb\x00\x00\x00\x00\x00\x00";
+        let (header, data) = Header::decode(packet).unwrap();
+
+        assert!(!header.is_display());
+        assert_eq!(data, b"");
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        assert_eq!(Header::decode(b"\x41\x01\x0b\x01"), Err(Error::TooShort));
+        assert_eq!(
+            Header::decode(b"\x81\x01\x0b\x01\x00\x00\x00\x00\x00\x00"),
+            Err(Error::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            Header::decode(b"\x41\x01\x23\x01\x00\x00\x00\x00\x00\x00"),
+            Err(Error::UnsupportedDataType(0x23))
+        );
+        assert_eq!(
+            Header::decode(b"\x41\x01\x0b\x01\x00\x00\x00\x00\x00\x06\xff\x00\x00"),
+            Err(Error::TooShort)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = Header {
+            flags: FLAG_PUSH,
+            sequence: 9,
+            format: PixelFormat::Rgb8,
+            destination: ID_DISPLAY,
+            offset: 2880,
+        };
+        let data = [1, 2, 3, 4, 5, 6];
+
+        let mut buf = [0; 64];
+        let len = header.encode(&data, &mut buf).unwrap();
+
+        assert_eq!(&buf[..4], b"\x41\x09\x0b\x01");
+        assert_eq!(Header::decode(&buf[..len]), Ok((header, &data[..])));
+    }
+
+    #[test]
+    fn fragments_large_frames() {
+        let data = [0; 1000 * 3];
+        let fragments = fragments(&data).collect::<Vec<_>>();
+
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0], (0, &data[..1440], false));
+        assert_eq!(fragments[1], (1440, &data[..1440], false));
+        assert_eq!(fragments[2], (2880, &data[..120], true));
+    }
+}
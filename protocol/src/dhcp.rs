@@ -0,0 +1,246 @@
+//! Just enough of a DHCP server to hand out addresses to clients of the provisioning access
+//! point, with the access point itself as their router and DNS server.
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+/// Length of the fixed part of a message, up to and including the magic cookie.
+const FIXED_LEN: usize = 240;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+
+/// Clients that can get an address at once. Past that, the oldest lease is reused.
+const LEASES: usize = 8;
+
+/// First address handed out, as the last octet within the server's /24.
+const FIRST_HOST: u8 = 100;
+
+const LEASE_SECS: u32 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooShort,
+    /// The message isn't a DHCP request from an Ethernet-like client.
+    NotRequest,
+    /// The message is a DHCP message other than a discover or request.
+    UnsupportedMessage,
+    BufferTooSmall,
+}
+
+pub struct Server {
+    address: [u8; 4],
+    /// Hardware addresses of clients, indexed by their host number after [`FIRST_HOST`].
+    leases: [Option<[u8; 6]>; LEASES],
+    next_lease: usize,
+}
+
+impl Server {
+    /// A server at `address`, handing out addresses in its /24.
+    pub fn new(address: [u8; 4]) -> Self {
+        Server {
+            address,
+            leases: [None; LEASES],
+            next_lease: 0,
+        }
+    }
+
+    /// Handles a message from a client, writing the reply to broadcast into `buf` and returning
+    /// its length.
+    pub fn handle(&mut self, message: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        if message.len() < FIXED_LEN {
+            return Err(Error::TooShort);
+        }
+
+        if message[0] != OP_REQUEST || message[1] != 1 || message[2] != 6 {
+            return Err(Error::NotRequest);
+        }
+
+        if message[236..240] != MAGIC_COOKIE {
+            return Err(Error::NotRequest);
+        }
+
+        let reply_type = match message_type(&message[FIXED_LEN..]) {
+            Some(DISCOVER) => OFFER,
+            Some(REQUEST) => ACK,
+            _ => return Err(Error::UnsupportedMessage),
+        };
+
+        let mut hardware_address = [0; 6];
+        hardware_address.copy_from_slice(&message[28..34]);
+        let client = self.lease(hardware_address);
+
+        let [a, b, c, _] = self.address;
+        let server = self.address;
+        let options: [&[u8]; 8] = [
+            &[OPTION_MESSAGE_TYPE, 1, reply_type],
+            &[
+                OPTION_SERVER_ID,
+                4,
+                server[0],
+                server[1],
+                server[2],
+                server[3],
+            ],
+            &[OPTION_LEASE_TIME, 4],
+            &LEASE_SECS.to_be_bytes(),
+            &[OPTION_SUBNET_MASK, 4, 255, 255, 255, 0],
+            &[OPTION_ROUTER, 4, server[0], server[1], server[2], server[3]],
+            &[
+                OPTION_DNS_SERVER,
+                4,
+                server[0],
+                server[1],
+                server[2],
+                server[3],
+            ],
+            &[OPTION_END],
+        ];
+
+        let len = FIXED_LEN + options.iter().map(|option| option.len()).sum::<usize>();
+        let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+        buf.fill(0);
+
+        buf[0] = OP_REPLY;
+        // Hardware type, address length, transaction id, seconds and flags, as in the request
+        buf[1..12].copy_from_slice(&message[1..12]);
+        buf[3] = 0;
+        buf[16..20].copy_from_slice(&[a, b, c, FIRST_HOST + client as u8]);
+        buf[20..24].copy_from_slice(&server);
+        buf[28..44].copy_from_slice(&message[28..44]);
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut pos = FIXED_LEN;
+        for option in options {
+            buf[pos..pos + option.len()].copy_from_slice(option);
+            pos += option.len();
+        }
+
+        Ok(len)
+    }
+
+    /// Finds or makes the lease of a client, returning its index.
+    fn lease(&mut self, hardware_address: [u8; 6]) -> usize {
+        if let Some(index) = self
+            .leases
+            .iter()
+            .position(|lease| *lease == Some(hardware_address))
+        {
+            return index;
+        }
+
+        let index = self.next_lease;
+        self.leases[index] = Some(hardware_address);
+        self.next_lease = (index + 1) % LEASES;
+
+        index
+    }
+}
+
+/// Finds the message type among the options.
+fn message_type(mut options: &[u8]) -> Option<u8> {
+    loop {
+        match *options {
+            [OPTION_PAD, ref rest @ ..] => options = rest,
+            [OPTION_END, ..] | [] => return None,
+            [OPTION_MESSAGE_TYPE, 1, kind, ..] => return Some(kind),
+            [_, len, ref rest @ ..] => options = rest.get(len as usize..)?,
+            [_] => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const CLIENT: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    fn request(client: [u8; 6], kind: u8) -> Vec<u8> {
+        let mut message = std::vec![0; FIXED_LEN];
+        message[0..4].copy_from_slice(&[OP_REQUEST, 1, 6, 0]);
+        message[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        message[10] = 0x80;
+        message[28..34].copy_from_slice(&client);
+        message[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        // Some padding and a host name before the message type
+        message.extend_from_slice(&[OPTION_PAD, 12, 5, b'p', b'h', b'o', b'n', b'e']);
+        message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, kind, OPTION_END]);
+        message
+    }
+
+    #[test]
+    fn offers_and_acknowledges_address() {
+        let mut server = Server::new([192, 168, 4, 1]);
+        let mut buf = [0; 576];
+
+        let len = server.handle(&request(CLIENT, DISCOVER), &mut buf).unwrap();
+        let offer = &buf[..len];
+
+        assert_eq!(offer[0], OP_REPLY);
+        assert_eq!(&offer[4..8], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(offer[10], 0x80);
+        assert_eq!(&offer[16..20], &[192, 168, 4, 100]);
+        assert_eq!(&offer[28..34], &CLIENT);
+        assert_eq!(message_type(&offer[FIXED_LEN..]), Some(OFFER));
+        assert!(offer
+            .windows(6)
+            .any(|option| option == [OPTION_DNS_SERVER, 4, 192, 168, 4, 1]));
+
+        let len = server.handle(&request(CLIENT, REQUEST), &mut buf).unwrap();
+        assert_eq!(&buf[16..20], &[192, 168, 4, 100]);
+        assert_eq!(message_type(&buf[FIXED_LEN..len]), Some(ACK));
+    }
+
+    #[test]
+    fn gives_clients_their_own_address() {
+        let mut server = Server::new([192, 168, 4, 1]);
+        let mut buf = [0; 576];
+
+        let mut other = CLIENT;
+        other[5] = 0x66;
+
+        server.handle(&request(CLIENT, DISCOVER), &mut buf).unwrap();
+        server.handle(&request(other, DISCOVER), &mut buf).unwrap();
+        assert_eq!(&buf[16..20], &[192, 168, 4, 101]);
+
+        server.handle(&request(CLIENT, REQUEST), &mut buf).unwrap();
+        assert_eq!(&buf[16..20], &[192, 168, 4, 100]);
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        let mut server = Server::new([192, 168, 4, 1]);
+        let mut buf = [0; 576];
+
+        // Release
+        assert_eq!(
+            server.handle(&request(CLIENT, 7), &mut buf),
+            Err(Error::UnsupportedMessage)
+        );
+
+        let mut reply = request(CLIENT, DISCOVER);
+        reply[0] = OP_REPLY;
+        assert_eq!(server.handle(&reply, &mut buf), Err(Error::NotRequest));
+
+        assert_eq!(server.handle(&[0; 100], &mut buf), Err(Error::TooShort));
+    }
+}
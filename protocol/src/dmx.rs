@@ -0,0 +1,187 @@
+//! Mapping DMX universes, as sent by E1.31 and Art-Net, onto a strip.
+
+use crate::PixelFormat;
+
+/// Channels in a DMX universe.
+pub const UNIVERSE_CHANNELS: usize = 512;
+
+/// Maps consecutive universes onto consecutive parts of the strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniverseMap {
+    /// Universe holding the first LED.
+    pub start_universe: u16,
+    /// Channel (counting from zero) of the first pixel in every universe.
+    pub start_channel: u16,
+    /// Channels used for pixels in every universe, starting at `start_channel`.
+    pub channels_per_universe: u16,
+    pub format: PixelFormat,
+}
+
+impl UniverseMap {
+    /// Maps 170 RGB pixels to every universe, starting at `start_universe`.
+    pub const fn new(start_universe: u16) -> Self {
+        UniverseMap {
+            start_universe,
+            start_channel: 0,
+            channels_per_universe: 510,
+            format: PixelFormat::Rgb8,
+        }
+    }
+
+    pub fn pixels_per_universe(&self) -> usize {
+        self.channels_per_universe as usize / self.format.bytes_per_pixel()
+    }
+
+    /// Index of the first LED in `universe`, or `None` if the universe doesn't cover any of a strip
+    /// of `leds` LEDs.
+    pub fn offset(&self, universe: u16, leds: usize) -> Option<usize> {
+        let index = universe.checked_sub(self.start_universe)?;
+        let offset = index as usize * self.pixels_per_universe();
+
+        (self.pixels_per_universe() > 0 && offset < leds).then_some(offset)
+    }
+
+    /// The whole pixels in the channel data of a universe.
+    pub fn pixel_data<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        let start = (self.start_channel as usize).min(data.len());
+        let len = self.pixels_per_universe() * self.format.bytes_per_pixel();
+        let end = (start + len).min(data.len());
+        let data = &data[start..end];
+
+        &data[..data.len()
+            - data
+                .chunks_exact(self.format.bytes_per_pixel())
+                .remainder()
+                .len()]
+    }
+
+    /// Universes covering a strip of `leds` LEDs.
+    pub fn universes(&self, leds: usize) -> impl Iterator<Item = u16> {
+        let count = leds.div_ceil(self.pixels_per_universe().max(1));
+        (self.start_universe..).take(count)
+    }
+}
+
+/// Decides when a frame spread over several universes is complete.
+///
+/// Senders go through their universes in order every frame, so a frame is shown once the
+/// highest universe seen so far arrives. Only universes on the strip should be passed in, see
+/// [`UniverseMap::offset`], so stray ones don't hold back every frame.
+#[derive(Debug, Default)]
+pub struct FrameTracker {
+    /// Highest universe of the last complete cycle through the universes.
+    highest: Option<u16>,
+    /// Highest universe of the cycle being received.
+    cycle_highest: Option<u16>,
+    previous: Option<u16>,
+}
+
+impl FrameTracker {
+    /// Registers a received universe, returning whether the frame should now be shown.
+    pub fn receive(&mut self, universe: u16) -> bool {
+        // Going back means the sender started over, having sent every universe it uses. That
+        // may be fewer than before, so the highest one is only kept for a cycle.
+        if self.previous.is_some_and(|previous| universe <= previous) {
+            self.highest = self.cycle_highest.take();
+        }
+        self.previous = Some(universe);
+        self.cycle_highest = self.cycle_highest.max(Some(universe));
+
+        match self.highest {
+            Some(highest) if universe < highest => false,
+            _ => {
+                self.highest = Some(universe);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn maps_universes_to_pixels() {
+        let map = UniverseMap::new(1);
+
+        assert_eq!(map.offset(0, 341), None);
+        assert_eq!(map.offset(1, 341), Some(0));
+        assert_eq!(map.offset(3, 341), Some(340));
+        assert_eq!(map.offset(4, 341), None);
+        assert_eq!(map.offset(u16::MAX, 341), None);
+        assert_eq!(map.universes(341).collect::<Vec<_>>(), [1, 2, 3]);
+
+        let data = [1; 512];
+        assert_eq!(map.pixel_data(&data).len(), 510);
+        assert_eq!(map.pixel_data(&data[..100]).len(), 99);
+    }
+
+    #[test]
+    fn maps_custom_channels() {
+        let map = UniverseMap {
+            start_universe: 0,
+            start_channel: 2,
+            channels_per_universe: 400,
+            format: PixelFormat::Rgbw8,
+        };
+
+        assert_eq!(map.pixels_per_universe(), 100);
+        assert_eq!(map.offset(2, 300), Some(200));
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(map.pixel_data(&data), &data[2..254]);
+    }
+
+    #[test]
+    fn shows_frame_after_highest_universe() {
+        let mut tracker = FrameTracker::default();
+
+        assert!(tracker.receive(1));
+        assert!(tracker.receive(2));
+        assert!(tracker.receive(3));
+
+        assert!(!tracker.receive(1));
+        assert!(!tracker.receive(2));
+        assert!(tracker.receive(3));
+    }
+
+    #[test]
+    fn recovers_from_stray_universes() {
+        let mut tracker = FrameTracker::default();
+
+        assert!(tracker.receive(1));
+        assert!(tracker.receive(2));
+        // Not on the strip, but sent by something else
+        assert!(tracker.receive(9));
+
+        // Held back for a cycle, then shown after the highest one again
+        assert!(!tracker.receive(1));
+        assert!(!tracker.receive(2));
+        assert!(!tracker.receive(1));
+        assert!(tracker.receive(2));
+        assert!(!tracker.receive(1));
+        assert!(tracker.receive(2));
+    }
+
+    #[test]
+    fn follows_senders_using_fewer_universes() {
+        let mut tracker = FrameTracker::default();
+
+        for universe in [1, 2, 3, 1, 2, 3] {
+            tracker.receive(universe);
+        }
+
+        assert!(!tracker.receive(1));
+        assert!(!tracker.receive(2));
+        assert!(!tracker.receive(1));
+        assert!(tracker.receive(2));
+
+        // A single universe is a whole frame
+        let mut tracker = FrameTracker::default();
+        assert!(tracker.receive(1));
+        assert!(tracker.receive(1));
+    }
+}
@@ -0,0 +1,159 @@
+//! Answering every DNS query with a single address, so clients of the provisioning access point
+//! end up at its captive portal whatever they look up.
+
+pub const PORT: u16 = 53;
+
+pub const HEADER_LEN: usize = 12;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_ANY: u16 = 255;
+pub const CLASS_IN: u16 = 1;
+
+/// Seconds clients may cache answers for. Kept short, as the answers are only true while
+/// provisioning.
+const TTL: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooShort,
+    /// The message is a response, or a query other than a standard one with a single question.
+    Unsupported,
+    /// A name is malformed or uses compression where it can't.
+    BadName,
+    BufferTooSmall,
+}
+
+/// Returns the length of the encoded name at the start of `data`.
+///
+/// Compression pointers end a name, so they're accepted without being followed.
+pub fn name_len(data: &[u8]) -> Result<usize, Error> {
+    let mut pos = 0;
+
+    loop {
+        let len = *data.get(pos).ok_or(Error::TooShort)? as usize;
+
+        match len {
+            0 => return Ok(pos + 1),
+            0xc0..=0xff => {
+                data.get(pos + 1).ok_or(Error::TooShort)?;
+                return Ok(pos + 2);
+            }
+            0x40..=0xbf => return Err(Error::BadName),
+            _ => pos += 1 + len,
+        }
+    }
+}
+
+/// Builds a response to `query` into `buf`, answering its question with `address` if it asks for
+/// an IPv4 address, and returns the response's length.
+pub fn answer_all(query: &[u8], address: [u8; 4], buf: &mut [u8]) -> Result<usize, Error> {
+    if query.len() < HEADER_LEN {
+        return Err(Error::TooShort);
+    }
+
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+
+    // Only standard queries
+    if flags & 0xf800 != 0 || questions != 1 {
+        return Err(Error::Unsupported);
+    }
+
+    let question_end = HEADER_LEN + name_len(&query[HEADER_LEN..])? + 4;
+    let question = query.get(HEADER_LEN..question_end).ok_or(Error::TooShort)?;
+
+    let kind = u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+    let class = u16::from_be_bytes([question[question.len() - 2], question[question.len() - 1]]);
+    let answers = u16::from((kind == TYPE_A || kind == TYPE_ANY) && class == CLASS_IN);
+
+    let answer_len = if answers > 0 { 16 } else { 0 };
+    let len = question_end + answer_len;
+    let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+
+    // Response, keeping the recursion desired bit, with recursion available
+    let response_flags = 0x8080 | (flags & 0x0100);
+
+    buf[0..2].copy_from_slice(&query[0..2]);
+    buf[2..4].copy_from_slice(&response_flags.to_be_bytes());
+    buf[4..6].copy_from_slice(&1u16.to_be_bytes());
+    buf[6..8].copy_from_slice(&answers.to_be_bytes());
+    buf[8..12].fill(0);
+    buf[HEADER_LEN..question_end].copy_from_slice(question);
+
+    if answers > 0 {
+        let answer = &mut buf[question_end..];
+
+        // Pointer to the name in the question
+        answer[0..2].copy_from_slice(&(0xc000 | HEADER_LEN as u16).to_be_bytes());
+        answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&TTL.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&address);
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Query for `example.com` with the given type, as sent by `dig`.
+    fn query(kind: u16) -> [u8; 29] {
+        let mut query = [0; 29];
+        query[..12].copy_from_slice(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        query[12..25].copy_from_slice(b"\x07example\x03com\x00");
+        query[25..27].copy_from_slice(&kind.to_be_bytes());
+        query[27..29].copy_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn answers_address_queries() {
+        let mut buf = [0; 512];
+        let len = answer_all(&query(TYPE_A), [192, 168, 4, 1], &mut buf).unwrap();
+
+        assert_eq!(len, 45);
+        assert_eq!(
+            &buf[..12],
+            &[0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(&buf[12..29], &query(TYPE_A)[12..]);
+        assert_eq!(
+            &buf[29..45],
+            &[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 4, 1]
+        );
+    }
+
+    #[test]
+    fn answers_other_queries_without_records() {
+        let mut buf = [0; 512];
+
+        // AAAA
+        let len = answer_all(&query(28), [192, 168, 4, 1], &mut buf).unwrap();
+        assert_eq!(len, 29);
+        assert_eq!(&buf[6..8], &[0, 0]);
+    }
+
+    #[test]
+    fn rejects_unsupported_messages() {
+        let mut buf = [0; 512];
+
+        let mut response = query(TYPE_A);
+        response[2] |= 0x80;
+        assert_eq!(
+            answer_all(&response, [0; 4], &mut buf),
+            Err(Error::Unsupported)
+        );
+
+        assert_eq!(
+            answer_all(&query(TYPE_A)[..20], [0; 4], &mut buf),
+            Err(Error::TooShort)
+        );
+        assert_eq!(
+            answer_all(&query(TYPE_A), [0; 4], &mut buf[..40]),
+            Err(Error::BufferTooSmall)
+        );
+    }
+}
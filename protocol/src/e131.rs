@@ -0,0 +1,181 @@
+//! E1.31 (Streaming ACN, or sACN) data packets, as sent by lighting consoles.
+//!
+//! Only the fields needed to get at the DMX data are parsed. Synchronization and discovery
+//! packets are rejected with [`Error::UnsupportedVector`].
+
+/// UDP port E1.31 is sent to.
+pub const PORT: u16 = 5568;
+
+/// E1.31 counts universes from 1.
+pub const FIRST_UNIVERSE: u16 = 1;
+
+/// Length of all layers' headers up to the DMX start code.
+const HEADER_LEN: usize = 125;
+
+/// Length of a packet carrying a full universe.
+pub const MAX_PACKET_LEN: usize = HEADER_LEN + 1 + 512;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+
+const VECTOR_ROOT_DATA: u32 = 0x0000_0004;
+const VECTOR_FRAMING_DATA: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+
+/// Set in the options of packets meant for visualizers rather than actual lights.
+pub const OPTION_PREVIEW_DATA: u8 = 1 << 7;
+/// Set in the options of the last packets a source sends before going away.
+pub const OPTION_STREAM_TERMINATED: u8 = 1 << 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooShort,
+    /// The packet isn't an ACN packet at all.
+    NotE131,
+    /// The packet is an E1.31 packet other than DMX data.
+    UnsupportedVector(u32),
+    /// The DMX data doesn't start with the null start code, i.e. isn't dimmer levels.
+    UnsupportedStartCode(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub universe: u16,
+    pub priority: u8,
+    pub sequence: u8,
+    /// Combination of the `OPTION_*` constants.
+    pub options: u8,
+    /// Channel data, without the start code.
+    pub data: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn decode(packet: &'a [u8]) -> Result<Self, Error> {
+        if packet.len() < HEADER_LEN + 1 {
+            return Err(Error::TooShort);
+        }
+
+        if packet[0..4] != [0x00, 0x10, 0x00, 0x00] || &packet[4..16] != ACN_PACKET_IDENTIFIER {
+            return Err(Error::NotE131);
+        }
+
+        let root_vector = u32::from_be_bytes([packet[18], packet[19], packet[20], packet[21]]);
+        if root_vector != VECTOR_ROOT_DATA {
+            return Err(Error::UnsupportedVector(root_vector));
+        }
+
+        let framing_vector = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]);
+        if framing_vector != VECTOR_FRAMING_DATA {
+            return Err(Error::UnsupportedVector(framing_vector));
+        }
+
+        if packet[117] != VECTOR_DMP_SET_PROPERTY {
+            return Err(Error::UnsupportedVector(packet[117] as u32));
+        }
+
+        // Includes the start code
+        let count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+        let values = packet
+            .get(HEADER_LEN..HEADER_LEN + count)
+            .filter(|values| !values.is_empty())
+            .ok_or(Error::TooShort)?;
+
+        if values[0] != 0 {
+            return Err(Error::UnsupportedStartCode(values[0]));
+        }
+
+        Ok(Packet {
+            universe: u16::from_be_bytes([packet[113], packet[114]]),
+            priority: packet[108],
+            sequence: packet[111],
+            options: packet[112],
+            data: &values[1..],
+        })
+    }
+}
+
+/// Multicast group data for `universe` is sent to, if not sent to a device directly.
+pub fn multicast_address(universe: u16) -> [u8; 4] {
+    let [high, low] = universe.to_be_bytes();
+    [239, 255, high, low]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Builds a data packet the way a console like QLC+ sends it.
+    fn packet(universe: u16, start_code: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+
+        // Root layer
+        packet.extend_from_slice(&[0x00, 0x10, 0x00, 0x00]);
+        packet.extend_from_slice(ACN_PACKET_IDENTIFIER);
+        packet.extend_from_slice(&(0x7000 | (110 + data.len() as u16)).to_be_bytes());
+        packet.extend_from_slice(&VECTOR_ROOT_DATA.to_be_bytes());
+        packet.extend_from_slice(&[0xab; 16]);
+
+        // Framing layer
+        packet.extend_from_slice(&(0x7000 | (88 + data.len() as u16)).to_be_bytes());
+        packet.extend_from_slice(&VECTOR_FRAMING_DATA.to_be_bytes());
+        let mut source_name = [0; 64];
+        source_name[..4].copy_from_slice(b"QLC+");
+        packet.extend_from_slice(&source_name);
+        packet.push(100);
+        packet.extend_from_slice(&[0, 0]);
+        packet.push(42);
+        packet.push(0);
+        packet.extend_from_slice(&universe.to_be_bytes());
+
+        // DMP layer
+        packet.extend_from_slice(&(0x7000 | (11 + data.len() as u16)).to_be_bytes());
+        packet.extend_from_slice(&[VECTOR_DMP_SET_PROPERTY, 0xa1, 0x00, 0x00, 0x00, 0x01]);
+        packet.extend_from_slice(&(1 + data.len() as u16).to_be_bytes());
+        packet.push(start_code);
+        packet.extend_from_slice(data);
+
+        packet
+    }
+
+    #[test]
+    fn decodes_data_packet() {
+        let data = (0..=255).chain(0..=255).collect::<Vec<u8>>();
+        let packet = packet(7, 0, &data);
+
+        assert_eq!(packet.len(), 638);
+        assert_eq!(
+            Packet::decode(&packet),
+            Ok(Packet {
+                universe: 7,
+                priority: 100,
+                sequence: 42,
+                options: 0,
+                data: &data,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let mut data = packet(1, 0, &[1, 2, 3]);
+        assert_eq!(Packet::decode(&data[..100]), Err(Error::TooShort));
+
+        assert_eq!(
+            Packet::decode(&packet(1, 0xdd, &[1, 2, 3])),
+            Err(Error::UnsupportedStartCode(0xdd))
+        );
+
+        data[21] = 0x08;
+        assert_eq!(Packet::decode(&data), Err(Error::UnsupportedVector(8)));
+
+        data[4] = b'X';
+        assert_eq!(Packet::decode(&data), Err(Error::NotE131));
+    }
+
+    #[test]
+    fn multicast_address_contains_universe() {
+        assert_eq!(multicast_address(1), [239, 255, 0, 1]);
+        assert_eq!(multicast_address(0x1234), [239, 255, 0x12, 0x34]);
+    }
+}
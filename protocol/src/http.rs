@@ -0,0 +1,195 @@
+//! Just enough HTTP/1.1 for the small web pages the firmware serves.
+//!
+//! Requests are parsed from a buffer holding at least the request line and headers. The body
+//! follows [`Request::header_len`] and is [`Request::content_length`] bytes long, which the caller
+//! reads or streams itself.
+
+pub const PORT: u16 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The request line or a header can't be parsed.
+    Malformed,
+    BufferTooSmall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// The requested path, including any query string.
+    pub path: &'a str,
+    pub content_length: usize,
+    /// Length of the request line and headers, after which the body starts.
+    pub header_len: usize,
+}
+
+impl<'a> Request<'a> {
+    /// Parses the request line and headers, returning `None` if they're not complete yet.
+    pub fn parse(buf: &'a [u8]) -> Result<Option<Self>, Error> {
+        let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return Ok(None);
+        };
+
+        let head = core::str::from_utf8(&buf[..end]).map_err(|_| Error::Malformed)?;
+        let mut lines = head.split("\r\n");
+
+        let mut request_line = lines.next().ok_or(Error::Malformed)?.split(' ');
+        let (Some(method), Some(path), Some(_version), None) = (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) else {
+            return Err(Error::Malformed);
+        };
+
+        let mut content_length = 0;
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(Error::Malformed)?;
+
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| Error::Malformed)?;
+            }
+        }
+
+        Ok(Some(Request {
+            method,
+            path,
+            content_length,
+            header_len: end + 4,
+        }))
+    }
+}
+
+/// Writes a response's status line and headers into `buf`, returning their length. The body of
+/// `content_length` bytes is to be sent after it.
+pub fn response_header(
+    status: &str,
+    content_type: &str,
+    content_length: usize,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let mut writer = Writer { buf, len: 0 };
+
+    core::fmt::write(
+        &mut writer,
+        format_args!(
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {content_length}\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .map_err(|_| Error::BufferTooSmall)?;
+
+    Ok(writer.len)
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl core::fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+
+        Ok(())
+    }
+}
+
+/// Finds the field `name` in an `application/x-www-form-urlencoded` body and decodes its value
+/// into `out`.
+///
+/// Returns `None` if the field is missing, or doesn't fit in `out` or decode to UTF-8.
+pub fn form_value<'b>(body: &[u8], name: &str, out: &'b mut [u8]) -> Option<&'b str> {
+    let value = body.split(|&b| b == b'&').find_map(|field| {
+        let mut parts = field.splitn(2, |&b| b == b'=');
+        (parts.next()? == name.as_bytes()).then(|| parts.next().unwrap_or_default())
+    })?;
+
+    let mut len = 0;
+    let mut bytes = value.iter();
+    while let Some(&byte) = bytes.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = hex_digit(*bytes.next()?)?;
+                let low = hex_digit(*bytes.next()?)?;
+                high << 4 | low
+            }
+            byte => byte,
+        };
+
+        *out.get_mut(len)? = decoded;
+        len += 1;
+    }
+
+    core::str::from_utf8(&out[..len]).ok()
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request() {
+        let request = b"POST /save HTTP/1.1\r\nHost: 192.168.4.1\r\ncontent-length: 26\r\n\r\nssid=home&password=hunter2";
+
+        assert_eq!(
+            Request::parse(request),
+            Ok(Some(Request {
+                method: "POST",
+                path: "/save",
+                content_length: 26,
+                header_len: 62,
+            }))
+        );
+        assert_eq!(&request[62..], b"ssid=home&password=hunter2");
+    }
+
+    #[test]
+    fn waits_for_complete_headers() {
+        assert_eq!(Request::parse(b"GET / HTTP/1.1\r\nHost: x\r\n"), Ok(None));
+        assert_eq!(Request::parse(b"GET /\r\n\r\n"), Err(Error::Malformed));
+        assert_eq!(
+            Request::parse(b"GET / HTTP/1.1\r\nContent-Length: lots\r\n\r\n"),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn writes_response_header() {
+        let mut buf = [0; 128];
+        let len = response_header("200 OK", "text/html", 5, &mut buf).unwrap();
+
+        assert_eq!(
+            &buf[..len],
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5\r\nConnection: close\r\n\r\n"
+        );
+
+        assert_eq!(
+            response_header("200 OK", "text/html", 5, &mut buf[..10]),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn decodes_form_values() {
+        let body = b"ssid=My+Network%21&password=p%C3%A4ss%3D%26&empty=";
+        let mut out = [0; 32];
+
+        assert_eq!(form_value(body, "ssid", &mut out), Some("My Network!"));
+        assert_eq!(form_value(body, "password", &mut out), Some("päss=&"));
+        assert_eq!(form_value(body, "empty", &mut out), Some(""));
+        assert_eq!(form_value(body, "missing", &mut out), None);
+        assert_eq!(form_value(body, "ssid", &mut out[..4]), None);
+        assert_eq!(form_value(b"ssid=%4", "ssid", &mut out), None);
+    }
+}
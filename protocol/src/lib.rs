@@ -0,0 +1,105 @@
+//! Wire formats spoken between `control`, the firmware and other lighting software, the mDNS
+//! records devices are found by, plus the bits of HTTP, DNS and DHCP the firmware needs to be set
+//! up from a phone and the OTA data the bootloader reads.
+//!
+//! Everything in here is `no_std` and allocation free, so it can be used on the ESP32 as well as
+//! tested on the host.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+pub mod artnet;
+pub mod ddp;
+pub mod dhcp;
+pub mod dmx;
+pub mod dns;
+pub mod e131;
+pub mod http;
+pub mod mdns;
+pub mod ota;
+pub mod packet;
+pub mod settings;
+pub mod wled;
+
+/// Layout of the pixel data in a packet.
+///
+/// The discriminants are the format ids used in native packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb8 = 0,
+    Rgbw8 = 1,
+}
+
+impl PixelFormat {
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgbw8 => 4,
+        }
+    }
+
+    /// Iterates over the pixels in `data` as RGB, mixing a white channel into the others.
+    pub fn rgb_pixels(self, data: &[u8]) -> impl Iterator<Item = [u8; 3]> + '_ {
+        data.chunks_exact(self.bytes_per_pixel())
+            .map(move |pixel| match self {
+                PixelFormat::Rgb8 => [pixel[0], pixel[1], pixel[2]],
+                PixelFormat::Rgbw8 => [
+                    pixel[0].saturating_add(pixel[3]),
+                    pixel[1].saturating_add(pixel[3]),
+                    pixel[2].saturating_add(pixel[3]),
+                ],
+            })
+    }
+}
+
+/// Pixels for some of the LEDs of a strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pixels<'a> {
+    /// Pairs of a one byte LED index and RGB, as in WLED's WARLS mode.
+    Indexed(&'a [u8]),
+    /// Pixels for consecutive LEDs, starting at `offset`.
+    Range {
+        offset: usize,
+        format: PixelFormat,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> Pixels<'a> {
+    /// Index just past the last LED written to.
+    pub fn end(&self) -> usize {
+        match *self {
+            Pixels::Indexed(_) => self.iter().map(|(index, _)| index + 1).max().unwrap_or(0),
+            Pixels::Range {
+                offset,
+                format,
+                data,
+            } => offset + data.len() / format.bytes_per_pixel(),
+        }
+    }
+
+    /// Every pixel as RGB, with the index of its LED.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, [u8; 3])> + 'a {
+        let (indexed, range) = match *self {
+            Pixels::Indexed(data) => (Some(data), None),
+            Pixels::Range {
+                offset,
+                format,
+                data,
+            } => (None, Some((offset, format, data))),
+        };
+
+        let indexed = indexed
+            .into_iter()
+            .flat_map(|data| data.chunks_exact(4))
+            .map(|chunk| (chunk[0] as usize, [chunk[1], chunk[2], chunk[3]]));
+
+        let range = range
+            .into_iter()
+            .flat_map(|(offset, format, data)| (offset..).zip(format.rgb_pixels(data)));
+
+        indexed.chain(range)
+    }
+}
@@ -0,0 +1,855 @@
+//! Multicast DNS responses advertising a device as `<hostname>.local`, and as a DNS-SD service
+//! of type [`SERVICE`] so it can be found without knowing its name, plus the queries and response
+//! parsing needed to find it.
+
+use core::fmt::Write as _;
+
+use crate::{
+    dns::{self, Error, CLASS_IN, HEADER_LEN, TYPE_A, TYPE_ANY},
+    packet,
+};
+
+pub const PORT: u16 = 5353;
+pub const ADDRESS: [u8; 4] = [224, 0, 0, 251];
+
+/// Service type devices advertise themselves as.
+pub const SERVICE: &str = "_home-leds._udp.local";
+
+/// Name browsed to find every service type on the network.
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SRV: u16 = 33;
+
+/// Keys in the TXT record of the service.
+pub const TXT_LEDS: &str = "leds";
+pub const TXT_PROTOCOL: &str = "proto";
+pub const TXT_FIRMWARE: &str = "fw";
+
+/// Set in the class of a question to ask for a unicast response.
+const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// Set in the class of a record to have caches drop other records with its name and type.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// TTLs recommended by RFC 6762: records that include the hostname expire sooner.
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+/// Longest TTL in legacy unicast responses, as those resolvers can't be told about changes.
+const LEGACY_TTL: u32 = 10;
+
+const MAX_NAME_LEN: usize = 255;
+
+/// Records that can be answered with, as bits.
+const RECORD_A: u8 = 1 << 0;
+const RECORD_PTR: u8 = 1 << 1;
+const RECORD_SRV: u8 = 1 << 2;
+const RECORD_TXT: u8 = 1 << 3;
+const RECORD_SERVICES: u8 = 1 << 4;
+
+/// A device as advertised: its address, and what's listening there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Service<'a> {
+    /// Hostname, without `.local`. Also used as the name of the service instance.
+    pub hostname: &'a str,
+    pub address: [u8; 4],
+    /// Port native packets are received on.
+    pub port: u16,
+    pub leds: u16,
+    pub firmware_version: &'a str,
+}
+
+/// A response built by [`answer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    pub len: usize,
+    /// Whether the response should be sent to the querier only, rather than to the group.
+    pub unicast: bool,
+}
+
+/// Builds a response into `buf` answering the questions in `query`, sent from `source_port`,
+/// about `service`.
+///
+/// Queries not from the mDNS port come from plain DNS resolvers, which get a legacy unicast
+/// response as in RFC 6762 section 6.7: one repeating the id and questions, with short TTLs.
+///
+/// Returns `None` if none of the questions are about it.
+pub fn answer(
+    query: &[u8],
+    source_port: u16,
+    service: &Service,
+    buf: &mut [u8],
+) -> Result<Option<Response>, Error> {
+    if query.len() < HEADER_LEN {
+        return Err(Error::TooShort);
+    }
+
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+
+    // Only standard queries
+    if flags & 0xf800 != 0 {
+        return Err(Error::Unsupported);
+    }
+
+    let legacy = source_port != PORT;
+
+    let mut answers = 0;
+    let mut unicast = true;
+    let mut pos = HEADER_LEN;
+
+    for _ in 0..questions {
+        let name = read_name(query, pos)?;
+
+        pos += dns::name_len(&query[pos..])?;
+        let fields = query.get(pos..pos + 4).ok_or(Error::TooShort)?;
+        pos += 4;
+
+        let kind = u16::from_be_bytes([fields[0], fields[1]]);
+        let class = u16::from_be_bytes([fields[2], fields[3]]);
+        if class & !UNICAST_RESPONSE != CLASS_IN {
+            continue;
+        }
+
+        let records = service.records(name.as_bytes(), kind);
+        if records != 0 {
+            answers |= records;
+            unicast &= class & UNICAST_RESPONSE != 0;
+        }
+    }
+
+    if answers == 0 {
+        return Ok(None);
+    }
+
+    // Save the querier asking again for what it's about to need
+    let mut additional = 0;
+    if answers & RECORD_PTR != 0 {
+        additional |= RECORD_SRV | RECORD_TXT | RECORD_A;
+    }
+    if answers & RECORD_SRV != 0 {
+        additional |= RECORD_A;
+    }
+
+    // Unicast responses have to be matched to the query
+    let echo = Echo {
+        id: if unicast || legacy {
+            u16::from_be_bytes([query[0], query[1]])
+        } else {
+            0
+        },
+        questions: if legacy {
+            (questions, &query[HEADER_LEN..pos])
+        } else {
+            (0, &[])
+        },
+        legacy,
+    };
+
+    let len = service.response(&echo, answers, additional & !answers, buf)?;
+
+    Ok(Some(Response {
+        len,
+        unicast: unicast || legacy,
+    }))
+}
+
+/// Builds an unsolicited response into `buf` with every record of `service`, to be sent to the
+/// group when it starts or changes, and returns its length.
+pub fn announce(service: &Service, buf: &mut [u8]) -> Result<usize, Error> {
+    service.response(
+        &Echo::default(),
+        RECORD_PTR | RECORD_SRV | RECORD_TXT | RECORD_A,
+        0,
+        buf,
+    )
+}
+
+/// What a response repeats of the query it answers.
+#[derive(Default)]
+struct Echo<'a> {
+    id: u16,
+    /// Number of questions, and the questions as they were in the query. Names in them can only
+    /// point at earlier questions, so they're still valid at the same place in the response.
+    questions: (u16, &'a [u8]),
+    /// Whether this answers a legacy unicast query, see [`answer`].
+    legacy: bool,
+}
+
+impl Service<'_> {
+    /// Records that answer a question for `name` of type `kind`.
+    fn records(&self, name: &[u8], kind: u16) -> u8 {
+        let asks = |record_kind| kind == record_kind || kind == TYPE_ANY;
+        let mut records = 0;
+
+        if is_name(name, &[self.hostname, "local"]) && asks(TYPE_A) {
+            records |= RECORD_A;
+        }
+        if is_name(name, &[SERVICE]) && asks(TYPE_PTR) {
+            records |= RECORD_PTR;
+        }
+        if is_name(name, &[self.hostname, SERVICE]) {
+            if asks(TYPE_SRV) {
+                records |= RECORD_SRV;
+            }
+            if asks(TYPE_TXT) {
+                records |= RECORD_TXT;
+            }
+        }
+        if is_name(name, &[SERVICES]) && asks(TYPE_PTR) {
+            records |= RECORD_SERVICES;
+        }
+
+        records
+    }
+
+    fn response(
+        &self,
+        echo: &Echo,
+        answers: u8,
+        additional: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let mut writer = Writer { buf, len: 0 };
+        let (questions, question_data) = echo.questions;
+
+        writer.u16(echo.id)?;
+        // Authoritative response
+        writer.u16(0x8400)?;
+        writer.u16(questions)?;
+        writer.u16(answers.count_ones() as u16)?;
+        writer.u16(0)?;
+        writer.u16(additional.count_ones() as u16)?;
+        writer.bytes(question_data)?;
+
+        self.write_records(&mut writer, answers, echo.legacy)?;
+        self.write_records(&mut writer, additional, echo.legacy)?;
+
+        Ok(writer.len)
+    }
+
+    fn write_records(&self, writer: &mut Writer, records: u8, legacy: bool) -> Result<(), Error> {
+        let host = [self.hostname, "local"];
+        let instance = [self.hostname, SERVICE];
+
+        // Plain DNS resolvers would take the cache-flush bit as part of the class
+        let (host_ttl, other_ttl, cache_flush) = if legacy {
+            (LEGACY_TTL, LEGACY_TTL, 0)
+        } else {
+            (HOST_TTL, OTHER_TTL, CACHE_FLUSH)
+        };
+
+        if records & RECORD_PTR != 0 {
+            writer.record(&[SERVICE], TYPE_PTR, CLASS_IN, other_ttl, |writer| {
+                writer.name(&instance)
+            })?;
+        }
+        if records & RECORD_SERVICES != 0 {
+            writer.record(&[SERVICES], TYPE_PTR, CLASS_IN, other_ttl, |writer| {
+                writer.name(&[SERVICE])
+            })?;
+        }
+        if records & RECORD_SRV != 0 {
+            writer.record(
+                &instance,
+                TYPE_SRV,
+                CLASS_IN | cache_flush,
+                host_ttl,
+                |writer| {
+                    // Priority and weight
+                    writer.u16(0)?;
+                    writer.u16(0)?;
+                    writer.u16(self.port)?;
+                    writer.name(&host)
+                },
+            )?;
+        }
+        if records & RECORD_TXT != 0 {
+            writer.record(
+                &instance,
+                TYPE_TXT,
+                CLASS_IN | cache_flush,
+                other_ttl,
+                |writer| {
+                    writer.txt(format_args!("{TXT_LEDS}={}", self.leds))?;
+                    writer.txt(format_args!("{TXT_PROTOCOL}={}", packet::VERSION))?;
+                    writer.txt(format_args!("{TXT_FIRMWARE}={}", self.firmware_version))
+                },
+            )?;
+        }
+        if records & RECORD_A != 0 {
+            writer.record(&host, TYPE_A, CLASS_IN | cache_flush, host_ttl, |writer| {
+                writer.bytes(&self.address)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds a query into `buf` with a question for each name, given as parts to be joined by dots,
+/// and type, and returns its length.
+///
+/// Asks for unicast responses, so they can be received without listening on the mDNS port.
+pub fn query(questions: &[(&[&str], u16)], buf: &mut [u8]) -> Result<usize, Error> {
+    let mut writer = Writer { buf, len: 0 };
+
+    writer.u16(0)?;
+    writer.u16(0)?;
+    writer.u16(questions.len() as u16)?;
+    writer.bytes(&[0; 6])?;
+
+    for (name, kind) in questions {
+        writer.name(name)?;
+        writer.u16(*kind)?;
+        writer.u16(CLASS_IN | UNICAST_RESPONSE)?;
+    }
+
+    Ok(writer.len)
+}
+
+/// A name read from a message, in dotted form.
+#[derive(Clone, Copy)]
+pub struct Name {
+    bytes: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+impl Name {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// Whether this is `parts` joined by dots, ignoring case as DNS does.
+    pub fn is(&self, parts: &[&str]) -> bool {
+        is_name(self.as_bytes(), parts)
+    }
+
+    /// The first label, like the hostname in `<hostname>.local`.
+    pub fn first_label(&self) -> &[u8] {
+        self.as_bytes()
+            .split(|&b| b == b'.')
+            .next()
+            .unwrap_or_default()
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes().eq_ignore_ascii_case(other.as_bytes())
+    }
+}
+
+impl Eq for Name {}
+
+impl core::fmt::Debug for Name {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match core::str::from_utf8(self.as_bytes()) {
+            Ok(name) => write!(f, "{name:?}"),
+            Err(_) => write!(f, "{:?}", self.as_bytes()),
+        }
+    }
+}
+
+/// A resource record in a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub name: Name,
+    pub ttl: u32,
+    pub data: RecordData<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordData<'a> {
+    A([u8; 4]),
+    Ptr(Name),
+    Srv {
+        port: u16,
+        target: Name,
+    },
+    /// Length-prefixed strings, see [`txt_value`].
+    Txt(&'a [u8]),
+    /// A record of another type, or a malformed one.
+    Other(u16),
+}
+
+/// Iterates over every record in the response `message`, in any section.
+pub fn records(message: &[u8]) -> Result<Records<'_>, Error> {
+    if message.len() < HEADER_LEN {
+        return Err(Error::TooShort);
+    }
+
+    let count = |i: usize| u16::from_be_bytes([message[i], message[i + 1]]);
+
+    // Only responses
+    if message[2] & 0x80 == 0 {
+        return Err(Error::Unsupported);
+    }
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..count(4) {
+        pos += dns::name_len(message.get(pos..).ok_or(Error::TooShort)?)? + 4;
+    }
+
+    Ok(Records {
+        message,
+        pos,
+        remaining: count(6) as usize + count(8) as usize + count(10) as usize,
+    })
+}
+
+/// Iterator returned by [`records`]. Stops after the first error.
+pub struct Records<'a> {
+    message: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> Records<'a> {
+    fn read(&mut self) -> Result<Record<'a>, Error> {
+        let message = self.message;
+        let name = read_name(message, self.pos)?;
+
+        let pos = self.pos + dns::name_len(message.get(self.pos..).ok_or(Error::TooShort)?)?;
+        let fields = message.get(pos..pos + 10).ok_or(Error::TooShort)?;
+
+        let kind = u16::from_be_bytes([fields[0], fields[1]]);
+        let ttl = u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]);
+        let data_len = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+
+        let data_pos = pos + 10;
+        let data = message
+            .get(data_pos..data_pos + data_len)
+            .ok_or(Error::TooShort)?;
+        self.pos = data_pos + data_len;
+
+        let data = match (kind, data) {
+            (TYPE_A, &[a, b, c, d]) => RecordData::A([a, b, c, d]),
+            (TYPE_PTR, _) => RecordData::Ptr(read_name(message, data_pos)?),
+            (TYPE_SRV, &[_, _, _, _, port_high, port_low, _, ..]) => RecordData::Srv {
+                port: u16::from_be_bytes([port_high, port_low]),
+                target: read_name(message, data_pos + 6)?,
+            },
+            (TYPE_TXT, _) => RecordData::Txt(data),
+            _ => RecordData::Other(kind),
+        };
+
+        Ok(Record { name, ttl, data })
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let record = self.read();
+        self.remaining = if record.is_ok() {
+            self.remaining - 1
+        } else {
+            0
+        };
+
+        Some(record)
+    }
+}
+
+/// Finds the value of `key` in TXT record data.
+pub fn txt_value<'a>(data: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let mut rest = data;
+
+    while let Some((&len, after)) = rest.split_first() {
+        let entry = after.get(..len as usize)?;
+        rest = &after[len as usize..];
+
+        if let Some(value) = entry
+            .strip_prefix(key.as_bytes())
+            .and_then(|value| value.strip_prefix(b"="))
+        {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Reads the name at `pos` in `message`, following compression pointers.
+fn read_name(message: &[u8], mut pos: usize) -> Result<Name, Error> {
+    let mut name = Name {
+        bytes: [0; MAX_NAME_LEN],
+        len: 0,
+    };
+
+    loop {
+        let label_len = *message.get(pos).ok_or(Error::TooShort)? as usize;
+
+        match label_len {
+            0 => return Ok(name),
+            0xc0..=0xff => {
+                let low = *message.get(pos + 1).ok_or(Error::TooShort)? as usize;
+                let target = (label_len & 0x3f) << 8 | low;
+
+                // Only backwards, so pointers can't loop
+                if target >= pos {
+                    return Err(Error::BadName);
+                }
+                pos = target;
+            }
+            0x40..=0xbf => return Err(Error::BadName),
+            _ => {
+                let label = message
+                    .get(pos + 1..pos + 1 + label_len)
+                    .ok_or(Error::TooShort)?;
+
+                if name.len > 0 {
+                    *name.bytes.get_mut(name.len).ok_or(Error::BadName)? = b'.';
+                    name.len += 1;
+                }
+                name.bytes
+                    .get_mut(name.len..name.len + label_len)
+                    .ok_or(Error::BadName)?
+                    .copy_from_slice(label);
+
+                name.len += label_len;
+                pos += 1 + label_len;
+            }
+        }
+    }
+}
+
+/// Whether the dotted `name` is `parts` joined by dots, ignoring case as DNS does.
+fn is_name(name: &[u8], parts: &[&str]) -> bool {
+    let mut rest = name;
+
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            match rest.split_first() {
+                Some((b'.', after)) => rest = after,
+                _ => return false,
+            }
+        }
+
+        match rest.get(..part.len()) {
+            Some(label) if label.eq_ignore_ascii_case(part.as_bytes()) => {
+                rest = &rest[part.len()..]
+            }
+            _ => return false,
+        }
+    }
+
+    rest.is_empty()
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.len = end;
+
+        Ok(())
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Writes `parts` joined by dots as a name, without compression.
+    fn name(&mut self, parts: &[&str]) -> Result<(), Error> {
+        for label in parts.iter().flat_map(|part| part.split('.')) {
+            if label.is_empty() || label.len() > 63 {
+                return Err(Error::BadName);
+            }
+
+            self.bytes(&[label.len() as u8])?;
+            self.bytes(label.as_bytes())?;
+        }
+
+        self.bytes(&[0])
+    }
+
+    fn record(
+        &mut self,
+        name: &[&str],
+        kind: u16,
+        class: u16,
+        ttl: u32,
+        data: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.name(name)?;
+        self.u16(kind)?;
+        self.u16(class)?;
+        self.bytes(&ttl.to_be_bytes())?;
+
+        // Data length, filled in once the data is written
+        let len_pos = self.len;
+        self.u16(0)?;
+        data(self)?;
+
+        let data_len = (self.len - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&data_len.to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Writes a length-prefixed string of TXT record data.
+    fn txt(&mut self, args: core::fmt::Arguments) -> Result<(), Error> {
+        let len_pos = self.len;
+        self.bytes(&[0])?;
+        self.write_fmt(args).map_err(|_| Error::BufferTooSmall)?;
+
+        self.buf[len_pos] = (self.len - len_pos - 1) as u8;
+
+        Ok(())
+    }
+}
+
+impl core::fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.bytes(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const SERVICE_INFO: Service = Service {
+        hostname: "desk",
+        address: [192, 168, 1, 50],
+        port: packet::PORT,
+        leds: 100,
+        firmware_version: "0.1.0",
+    };
+
+    /// Query with a question for each of `questions`, as name, type and class.
+    fn query(questions: &[(&str, u16, u16)]) -> Vec<u8> {
+        let mut query = std::vec![0x12, 0x34, 0, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0];
+
+        for (name, kind, class) in questions {
+            for label in name.split('.') {
+                query.push(label.len() as u8);
+                query.extend_from_slice(label.as_bytes());
+            }
+            query.push(0);
+            query.extend_from_slice(&kind.to_be_bytes());
+            query.extend_from_slice(&class.to_be_bytes());
+        }
+
+        query
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn answers_address_queries() {
+        let mut buf = [0; 512];
+        let response = answer(
+            &query(&[("DESK.local", TYPE_A, CLASS_IN)]),
+            PORT,
+            &SERVICE_INFO,
+            &mut buf,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert!(!response.unicast);
+        assert_eq!(response.len, 38);
+        assert_eq!(&buf[..12], &[0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(&buf[12..24], b"\x04desk\x05local\x00");
+        assert_eq!(
+            &buf[24..38],
+            &[0, 1, 0x80, 1, 0, 0, 0, 120, 0, 4, 192, 168, 1, 50]
+        );
+    }
+
+    #[test]
+    fn answers_browsing_with_everything_needed_to_connect() {
+        let mut buf = [0; 512];
+        let response = answer(
+            &query(&[(SERVICE, TYPE_PTR, CLASS_IN)]),
+            PORT,
+            &SERVICE_INFO,
+            &mut buf,
+        )
+        .unwrap()
+        .unwrap();
+        let response = &buf[..response.len];
+
+        // PTR, then SRV, TXT and A as additional records
+        assert_eq!(&response[4..12], &[0, 0, 0, 1, 0, 0, 0, 3]);
+        assert!(contains(
+            response,
+            b"\x04desk\x0a_home-leds\x04_udp\x05local\x00"
+        ));
+        assert!(contains(response, &[0, 0, 0, 0, 0x1e, 0x61, 4, b'd']));
+        assert!(contains(response, b"\x08leds=100\x07proto=1\x08fw=0.1.0"));
+        assert!(contains(response, &[192, 168, 1, 50]));
+    }
+
+    #[test]
+    fn honours_unicast_and_any_queries() {
+        let mut buf = [0; 512];
+        let query = query(&[(
+            "desk._home-leds._udp.local",
+            TYPE_ANY,
+            CLASS_IN | UNICAST_RESPONSE,
+        )]);
+        let response = answer(&query, PORT, &SERVICE_INFO, &mut buf)
+            .unwrap()
+            .unwrap();
+
+        // SRV and TXT, with A as an additional record
+        assert!(response.unicast);
+        assert_eq!(&buf[..2], &[0x12, 0x34]);
+        assert_eq!(&buf[4..12], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn answers_legacy_unicast_queries() {
+        let mut buf = [0; 512];
+        let query = query(&[("desk.local", TYPE_A, CLASS_IN)]);
+        let response = answer(&query, 53000, &SERVICE_INFO, &mut buf)
+            .unwrap()
+            .unwrap();
+
+        // Same id and question, and an answer without cache-flush bit and with a short TTL
+        assert!(response.unicast);
+        assert_eq!(response.len, query.len() + 26);
+        assert_eq!(&buf[..12], &[0x12, 0x34, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(&buf[12..query.len()], &query[12..]);
+        assert_eq!(
+            &buf[query.len() + 12..response.len],
+            &[0, 1, 0, 1, 0, 0, 0, 10, 0, 4, 192, 168, 1, 50]
+        );
+
+        // Records can still be read past the question
+        let records = records(&buf[..response.len]).unwrap();
+        assert_eq!(records.count(), 1);
+    }
+
+    #[test]
+    fn follows_compressed_names() {
+        let mut query = query(&[("desk.local", 28, CLASS_IN)]);
+        // Second question pointing at the first one's name
+        query[5] = 2;
+        query.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+
+        let mut buf = [0; 512];
+        let response = answer(&query, PORT, &SERVICE_INFO, &mut buf)
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[4..12], &[0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response.len, 38);
+
+        // Pointers only go backwards
+        let mut looping = self::query(&[]);
+        looping[5] = 1;
+        looping.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(
+            answer(&looping, PORT, &SERVICE_INFO, &mut buf),
+            Err(Error::BadName)
+        );
+    }
+
+    #[test]
+    fn ignores_other_names() {
+        let mut buf = [0; 512];
+        let query = query(&[
+            ("other.local", TYPE_A, CLASS_IN),
+            ("_http._tcp.local", TYPE_PTR, CLASS_IN),
+            ("desk.local", TYPE_TXT, CLASS_IN),
+        ]);
+
+        assert_eq!(answer(&query, PORT, &SERVICE_INFO, &mut buf), Ok(None));
+    }
+
+    #[test]
+    fn builds_queries() {
+        let mut buf = [0; 512];
+        let len = super::query(&[(&["desk", "local"], TYPE_A)], &mut buf).unwrap();
+
+        // Same as a query from a resolver, apart from the id
+        assert_eq!(
+            &buf[2..len],
+            &query(&[("desk.local", TYPE_A, CLASS_IN | UNICAST_RESPONSE)])[2..]
+        );
+
+        let mut answer_buf = [0; 512];
+        let response = answer(&buf[..len], PORT, &SERVICE_INFO, &mut answer_buf)
+            .unwrap()
+            .unwrap();
+        assert!(response.unicast);
+    }
+
+    #[test]
+    fn parses_announced_records() {
+        let mut buf = [0; 512];
+        let len = announce(&SERVICE_INFO, &mut buf).unwrap();
+
+        let records = records(&buf[..len])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 4);
+
+        let RecordData::Ptr(instance) = records[0].data else {
+            panic!("expected PTR, got {:?}", records[0]);
+        };
+        assert!(records[0].name.is(&[SERVICE]));
+        assert!(instance.is(&["desk", SERVICE]));
+        assert_eq!(instance.first_label(), b"desk");
+
+        let RecordData::Srv { port, target } = records[1].data else {
+            panic!("expected SRV, got {:?}", records[1]);
+        };
+        assert_eq!(records[1].name, instance);
+        assert_eq!(port, packet::PORT);
+        assert!(target.is(&["desk", "local"]));
+
+        let RecordData::Txt(txt) = records[2].data else {
+            panic!("expected TXT, got {:?}", records[2]);
+        };
+        assert_eq!(txt_value(txt, TXT_LEDS), Some(&b"100"[..]));
+        assert_eq!(txt_value(txt, TXT_FIRMWARE), Some(&b"0.1.0"[..]));
+        assert_eq!(txt_value(txt, "missing"), None);
+
+        assert_eq!(records[3].name, target);
+        assert_eq!(records[3].ttl, HOST_TTL);
+        assert_eq!(records[3].data, RecordData::A([192, 168, 1, 50]));
+
+        // Queries aren't responses
+        let query = query(&[("desk.local", TYPE_A, CLASS_IN)]);
+        assert!(matches!(super::records(&query), Err(Error::Unsupported)));
+    }
+
+    #[test]
+    fn announces_every_record() {
+        let mut buf = [0; 512];
+        let len = announce(&SERVICE_INFO, &mut buf).unwrap();
+
+        assert_eq!(&buf[..12], &[0, 0, 0x84, 0, 0, 0, 0, 4, 0, 0, 0, 0]);
+        assert!(contains(&buf[..len], b"\x08leds=100"));
+        assert_eq!(
+            announce(&SERVICE_INFO, &mut buf[..100]),
+            Err(Error::BufferTooSmall)
+        );
+    }
+}
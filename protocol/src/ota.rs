@@ -0,0 +1,294 @@
+//! The OTA data partition, through which the bootloader picks which of the two app partitions to
+//! boot.
+//!
+//! It holds two copies of a 32 byte entry, one per flash sector, in the layout ESP-IDF uses:
+//!
+//! ```text
+//! 0               4                   24              28         32
+//! +---------------+-------------------+---------------+----------+
+//! | sequence (LE) | label (unused)    | state (LE)    | CRC (LE) |
+//! +---------------+-------------------+---------------+----------+
+//! ```
+//!
+//! The bootloader boots app partition `(sequence - 1) % 2` of the valid entry with the highest
+//! sequence number, skipping entries marked invalid. Updates write a new entry to the other
+//! sector, so the previous one is still there to roll back to.
+
+pub const ENTRY_LEN: usize = 32;
+
+/// Number of app partitions.
+const PARTITIONS: u32 = 2;
+
+/// First byte of an app image.
+pub const IMAGE_MAGIC: u8 = 0xe9;
+
+/// Where an image is in its life, as far as the app itself keeps track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageState {
+    /// Just written, not booted yet.
+    New,
+    /// Booted, but not confirmed to work yet.
+    PendingVerify,
+    /// Confirmed to work.
+    Valid,
+    /// Failed to confirm itself, so it won't be booted again.
+    Invalid,
+    Aborted,
+    /// Written without tracking state, e.g. by flashing tools.
+    Undefined,
+}
+
+impl ImageState {
+    fn to_u32(self) -> u32 {
+        match self {
+            ImageState::New => 0,
+            ImageState::PendingVerify => 1,
+            ImageState::Valid => 2,
+            ImageState::Invalid => 3,
+            ImageState::Aborted => 4,
+            ImageState::Undefined => u32::MAX,
+        }
+    }
+
+    fn from_u32(value: u32) -> Self {
+        match value {
+            0 => ImageState::New,
+            1 => ImageState::PendingVerify,
+            2 => ImageState::Valid,
+            3 => ImageState::Invalid,
+            4 => ImageState::Aborted,
+            _ => ImageState::Undefined,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub sequence: u32,
+    pub state: ImageState,
+}
+
+impl Entry {
+    /// Parses an entry, returning `None` if the sector is erased or the entry corrupt.
+    pub fn decode(data: &[u8; ENTRY_LEN]) -> Option<Self> {
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+
+        let sequence = word(0);
+        if sequence == u32::MAX || word(28) != crc(sequence) {
+            return None;
+        }
+
+        Some(Entry {
+            sequence,
+            state: ImageState::from_u32(word(24)),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; ENTRY_LEN] {
+        let mut data = [0xff; ENTRY_LEN];
+        data[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        data[24..28].copy_from_slice(&self.state.to_u32().to_le_bytes());
+        data[28..32].copy_from_slice(&crc(self.sequence).to_le_bytes());
+        data
+    }
+
+    /// Index of the app partition this entry boots.
+    pub fn partition(&self) -> usize {
+        (self.sequence.wrapping_sub(1) % PARTITIONS) as usize
+    }
+
+    /// Whether the bootloader considers booting this entry.
+    pub fn is_bootable(&self) -> bool {
+        !matches!(self.state, ImageState::Invalid | ImageState::Aborted)
+    }
+}
+
+/// Index of the entry the bootloader boots from, if any.
+pub fn selected(entries: &[Option<Entry>; 2]) -> Option<usize> {
+    (0..entries.len())
+        .filter(|&i| entries[i].is_some_and(|entry| entry.is_bootable()))
+        .max_by_key(|&i| entries[i].map(|entry| entry.sequence))
+}
+
+/// The entry that makes the bootloader boot `partition` from now on, with the index of the sector
+/// to write it to.
+pub fn next(entries: &[Option<Entry>; 2], partition: usize) -> (usize, Entry) {
+    let current = selected(entries);
+    let highest = entries
+        .iter()
+        .flatten()
+        .map(|entry| entry.sequence)
+        .max()
+        .unwrap_or(0);
+
+    let mut sequence = highest + 1;
+    while (sequence - 1) % PARTITIONS != partition as u32 {
+        sequence += 1;
+    }
+
+    let sector = current.map_or(0, |current| 1 - current);
+
+    (
+        sector,
+        Entry {
+            sequence,
+            state: ImageState::New,
+        },
+    )
+}
+
+/// Parses a SHA-256 digest from 64 hex digits.
+pub fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+/// The update key to store after setup was submitted with `new`, given the `stored` one, or `None`
+/// if the change has to be refused. Empty keys mean there is none.
+///
+/// Setup can be reached by anyone nearby, so replacing a stored key takes the `current` one. An
+/// empty `new` key keeps the stored one.
+pub fn change_update_key<'a>(stored: &'a str, current: &str, new: &'a str) -> Option<&'a str> {
+    if new.is_empty() {
+        return Some(stored);
+    }
+
+    // Compared in full, so the time taken doesn't give away how much of it was right
+    let matches = stored.len() == current.len()
+        && stored
+            .bytes()
+            .zip(current.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+
+    (stored.is_empty() || matches).then_some(new)
+}
+
+/// The CRC the bootloader checks entries with: CRC-32 of the sequence number, starting from
+/// zero instead of the usual all ones.
+fn crc(sequence: u32) -> u32 {
+    let mut crc = 0u32;
+
+    for byte in sequence.to_le_bytes() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_entries() {
+        let entry = Entry {
+            sequence: 1,
+            state: ImageState::PendingVerify,
+        };
+        let data = entry.encode();
+
+        assert_eq!(&data[28..], &0x4743_989au32.to_le_bytes());
+        assert_eq!(Entry::decode(&data), Some(entry));
+        assert_eq!(entry.partition(), 0);
+
+        assert_eq!(Entry::decode(&[0xff; ENTRY_LEN]), None);
+
+        let mut corrupt = data;
+        corrupt[0] = 2;
+        assert_eq!(Entry::decode(&corrupt), None);
+    }
+
+    #[test]
+    fn selects_highest_bootable_entry() {
+        let entry = |sequence, state| Some(Entry { sequence, state });
+
+        assert_eq!(selected(&[None, None]), None);
+        assert_eq!(selected(&[entry(1, ImageState::Undefined), None]), Some(0));
+        assert_eq!(
+            selected(&[entry(1, ImageState::Valid), entry(2, ImageState::New)]),
+            Some(1)
+        );
+        assert_eq!(
+            selected(&[entry(3, ImageState::Invalid), entry(2, ImageState::Valid)]),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn switches_partitions() {
+        let valid = Some(Entry {
+            sequence: 1,
+            state: ImageState::Valid,
+        });
+
+        // From a freshly flashed device
+        assert_eq!(
+            next(&[None, None], 1),
+            (
+                0,
+                Entry {
+                    sequence: 2,
+                    state: ImageState::New
+                }
+            )
+        );
+
+        // Keeping the current entry to roll back to
+        let (sector, entry) = next(&[valid, None], 1);
+        assert_eq!((sector, entry.sequence, entry.partition()), (1, 2, 1));
+
+        let (sector, entry) = next(&[valid, Some(entry)], 0);
+        assert_eq!((sector, entry.sequence, entry.partition()), (0, 3, 0));
+    }
+
+    #[test]
+    fn changes_update_keys_with_the_current_one() {
+        // Set up for the first time
+        assert_eq!(change_update_key("", "", "secret"), Some("secret"));
+        // Left empty
+        assert_eq!(change_update_key("secret", "", ""), Some("secret"));
+
+        assert_eq!(
+            change_update_key("secret", "secret", "other"),
+            Some("other")
+        );
+        assert_eq!(change_update_key("secret", "", "other"), None);
+        assert_eq!(change_update_key("secret", "secreT", "other"), None);
+        assert_eq!(change_update_key("secret", "secret2", "other"), None);
+    }
+
+    #[test]
+    fn parses_digests() {
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let digest = parse_digest(hex).unwrap();
+
+        assert_eq!(digest[0], 0xe3);
+        assert_eq!(digest[31], 0x55);
+        assert_eq!(parse_digest(&hex[1..]), None);
+        assert_eq!(parse_digest(&hex.replace('e', "g")), None);
+    }
+}
@@ -0,0 +1,315 @@
+//! The native home-leds packet: a small header followed by pixel data.
+//!
+//! ```text
+//! 0      2         3          4       5        6             8
+//! +------+---------+----------+-------+--------+-------------+---------------
+//! | "HL" | version | sequence | flags | format | offset (BE) | pixel data ...
+//! +------+---------+----------+-------+--------+-------------+---------------
+//! ```
+//!
+//! `offset` is the index of the LED the first pixel in the packet is written to. The number of
+//! pixels follows from the packet length and the pixel format.
+//!
+//! Frames that don't fit in a single packet are split into fragments sharing a sequence number,
+//! sent in order of increasing offset. The first fragment has [`FLAG_START`] set and the last one
+//! [`FLAG_PUSH`], and the receiver only shows the frame once every fragment in between arrived.
+//! A frame that fits in a single packet has both flags set.
+
+use crate::PixelFormat;
+
+/// UDP port the firmware listens on for native packets.
+pub const PORT: u16 = 7777;
+
+pub const MAGIC: [u8; 2] = *b"HL";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 8;
+
+/// Largest packet that fits in a single unfragmented UDP datagram on a 1500 byte MTU.
+pub const MAX_PACKET_LEN: usize = 1472;
+
+/// Set on the last fragment of a frame, telling the receiver to show it.
+pub const FLAG_PUSH: u8 = 1 << 0;
+
+/// Set on the first fragment of a frame.
+pub const FLAG_START: u8 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The packet is shorter than a header.
+    TooShort,
+    /// The packet doesn't start with [`MAGIC`].
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownPixelFormat(u8),
+    /// The pixel data doesn't consist of whole pixels.
+    PartialPixel,
+    /// The output buffer can't hold the encoded packet.
+    BufferTooSmall,
+}
+
+impl TryFrom<u8> for PixelFormat {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PixelFormat::Rgb8),
+            1 => Ok(PixelFormat::Rgbw8),
+            _ => Err(Error::UnknownPixelFormat(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Incremented (and wrapped) by the sender for every frame.
+    pub sequence: u8,
+    /// Combination of the `FLAG_*` constants.
+    pub flags: u8,
+    pub format: PixelFormat,
+    /// Index of the LED the first pixel is written to.
+    pub offset: u16,
+}
+
+impl Header {
+    /// Writes the header followed by `pixels` into `buf`, returning the length of the packet.
+    pub fn encode(&self, pixels: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        if !pixels
+            .chunks_exact(self.format.bytes_per_pixel())
+            .remainder()
+            .is_empty()
+        {
+            return Err(Error::PartialPixel);
+        }
+
+        let len = HEADER_LEN + pixels.len();
+        let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+
+        buf[0..2].copy_from_slice(&MAGIC);
+        buf[2] = VERSION;
+        buf[3] = self.sequence;
+        buf[4] = self.flags;
+        buf[5] = self.format as u8;
+        buf[6..8].copy_from_slice(&self.offset.to_be_bytes());
+        buf[HEADER_LEN..].copy_from_slice(pixels);
+
+        Ok(len)
+    }
+
+    /// Parses a packet into its header and pixel data.
+    pub fn decode(packet: &[u8]) -> Result<(Header, &[u8]), Error> {
+        if packet.len() < HEADER_LEN {
+            return Err(Error::TooShort);
+        }
+
+        if packet[0..2] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        if packet[2] != VERSION {
+            return Err(Error::UnsupportedVersion(packet[2]));
+        }
+
+        let header = Header {
+            sequence: packet[3],
+            flags: packet[4],
+            format: PixelFormat::try_from(packet[5])?,
+            offset: u16::from_be_bytes([packet[6], packet[7]]),
+        };
+
+        let pixels = &packet[HEADER_LEN..];
+        if !pixels
+            .chunks_exact(header.format.bytes_per_pixel())
+            .remainder()
+            .is_empty()
+        {
+            return Err(Error::PartialPixel);
+        }
+
+        Ok((header, pixels))
+    }
+}
+
+/// Splits a frame of pixel data into fragments that each fit in a [`MAX_PACKET_LEN`] packet.
+///
+/// Yields the offset, pixel data and `FLAG_*` flags of every fragment.
+pub fn fragments(pixels: &[u8], format: PixelFormat) -> impl Iterator<Item = (u16, &[u8], u8)> {
+    let pixels_per_packet = (MAX_PACKET_LEN - HEADER_LEN) / format.bytes_per_pixel();
+    let chunk_len = pixels_per_packet * format.bytes_per_pixel();
+    let count = pixels.len().div_ceil(chunk_len).max(1);
+
+    (0..count).map(move |index| {
+        let start = index * chunk_len;
+        let end = (start + chunk_len).min(pixels.len());
+
+        let mut flags = 0;
+        if index == 0 {
+            flags |= FLAG_START;
+        }
+        if index == count - 1 {
+            flags |= FLAG_PUSH;
+        }
+
+        (
+            (index * pixels_per_packet) as u16,
+            &pixels[start..end],
+            flags,
+        )
+    })
+}
+
+/// Tracks the fragments of incoming frames to tell when one is complete.
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    sequence: u8,
+    /// Offset the next fragment of the current frame should start at, if none of its fragments
+    /// went missing so far.
+    next_offset: Option<usize>,
+}
+
+impl Reassembly {
+    /// Registers a received fragment containing `pixel_count` pixels, returning whether it
+    /// completes a frame that should now be shown.
+    pub fn receive(&mut self, header: &Header, pixel_count: usize) -> bool {
+        let offset = header.offset as usize;
+
+        let contiguous = if header.flags & FLAG_START != 0 {
+            self.sequence = header.sequence;
+            true
+        } else {
+            header.sequence == self.sequence && self.next_offset == Some(offset)
+        };
+
+        self.next_offset = contiguous.then_some(offset + pixel_count);
+
+        if header.flags & FLAG_PUSH == 0 {
+            return false;
+        }
+
+        // Anything arriving with this sequence number after the push belongs to a stale frame
+        self.next_offset = None;
+        contiguous
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let header = Header {
+            sequence: 42,
+            flags: 0,
+            format: PixelFormat::Rgb8,
+            offset: 300,
+        };
+        let pixels = [255, 0, 0, 0, 255, 0];
+
+        let mut buf = [0; 64];
+        let len = header.encode(&pixels, &mut buf).unwrap();
+
+        assert_eq!(
+            &buf[..len],
+            b"HL\x01\x2a\x00\x00\x01\x2c\xff\x00\x00\x00\xff\x00"
+        );
+        assert_eq!(Header::decode(&buf[..len]), Ok((header, &pixels[..])));
+    }
+
+    #[test]
+    fn round_trip_rgbw() {
+        let header = Header {
+            sequence: 255,
+            flags: 0,
+            format: PixelFormat::Rgbw8,
+            offset: 0,
+        };
+        let pixels = [1, 2, 3, 4];
+
+        let mut buf = [0; 64];
+        let len = header.encode(&pixels, &mut buf).unwrap();
+
+        assert_eq!(Header::decode(&buf[..len]), Ok((header, &pixels[..])));
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        assert_eq!(Header::decode(b"HL\x01"), Err(Error::TooShort));
+        assert_eq!(
+            Header::decode(b"\xff\x00\x00\x00\xff\x00\x00\x00"),
+            Err(Error::BadMagic)
+        );
+        assert_eq!(
+            Header::decode(b"HL\x02\x00\x00\x00\x00\x00"),
+            Err(Error::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            Header::decode(b"HL\x01\x00\x00\x07\x00\x00"),
+            Err(Error::UnknownPixelFormat(7))
+        );
+        assert_eq!(
+            Header::decode(b"HL\x01\x00\x00\x00\x00\x00\xff\x00"),
+            Err(Error::PartialPixel)
+        );
+    }
+
+    #[test]
+    fn encode_checks_buffer_and_pixels() {
+        let header = Header {
+            sequence: 0,
+            flags: 0,
+            format: PixelFormat::Rgb8,
+            offset: 0,
+        };
+
+        assert_eq!(
+            header.encode(&[0; 6], &mut [0; 10]),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(
+            header.encode(&[0; 5], &mut [0; 64]),
+            Err(Error::PartialPixel)
+        );
+    }
+
+    #[test]
+    fn fragments_large_frames() {
+        let pixels = [7; 1000 * 3];
+        let fragments = fragments(&pixels, PixelFormat::Rgb8).collect::<Vec<_>>();
+
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0], (0, &pixels[..488 * 3], FLAG_START));
+        assert_eq!(fragments[1], (488, &pixels[..488 * 3], 0));
+        assert_eq!(fragments[2], (976, &pixels[..24 * 3], FLAG_PUSH));
+
+        let fragments = super::fragments(&[], PixelFormat::Rgb8).collect::<Vec<_>>();
+        assert_eq!(fragments, [(0, &[][..], FLAG_START | FLAG_PUSH)]);
+    }
+
+    #[test]
+    fn reassembles_complete_frames_only() {
+        let fragment = |sequence, offset, flags| Header {
+            sequence,
+            flags,
+            format: PixelFormat::Rgb8,
+            offset,
+        };
+        let mut reassembly = Reassembly::default();
+
+        assert!(!reassembly.receive(&fragment(1, 0, FLAG_START), 488));
+        assert!(!reassembly.receive(&fragment(1, 488, 0), 488));
+        assert!(reassembly.receive(&fragment(1, 976, FLAG_PUSH), 24));
+
+        // Middle fragment lost
+        assert!(!reassembly.receive(&fragment(2, 0, FLAG_START), 488));
+        assert!(!reassembly.receive(&fragment(2, 976, FLAG_PUSH), 24));
+
+        // First fragment lost
+        assert!(!reassembly.receive(&fragment(3, 488, 0), 488));
+        assert!(!reassembly.receive(&fragment(3, 976, FLAG_PUSH), 24));
+
+        // Partial update in a single packet
+        assert!(reassembly.receive(&fragment(4, 10, FLAG_START | FLAG_PUSH), 5));
+    }
+}
@@ -0,0 +1,458 @@
+//! Device settings, as stored in the firmware's flash and changed over the network.
+//!
+//! Settings are encoded as a list of records, so fields can be added without breaking stored
+//! settings:
+//!
+//! ```text
+//! +-----+--------+-----------------+-----
+//! | key | length | value ...       | ...
+//! +-----+--------+-----------------+-----
+//! ```
+//!
+//! Messages on the settings port start with [`MAGIC`](crate::packet::MAGIC), the protocol
+//! version and a command byte. [`Message::Set`] and [`Message::Current`] are followed by the
+//! settings records.
+
+use crate::{
+    dmx::{UniverseMap, UNIVERSE_CHANNELS},
+    packet::{MAGIC, VERSION},
+    PixelFormat,
+};
+
+/// UDP port the firmware listens on for settings messages.
+pub const PORT: u16 = 7778;
+
+pub const HEADER_LEN: usize = 4;
+
+/// Largest encoded settings message.
+pub const MAX_MESSAGE_LEN: usize = 64;
+
+const KEY_LEDS: u8 = 1;
+const KEY_COLOR_ORDER: u8 = 2;
+const KEY_DATA_PIN: u8 = 3;
+const KEY_START_UNIVERSE: u8 = 4;
+const KEY_START_CHANNEL: u8 = 5;
+const KEY_CHANNELS_PER_UNIVERSE: u8 = 6;
+const KEY_LOCAL_EFFECT: u8 = 7;
+const KEY_INTERPOLATE: u8 = 8;
+
+const COMMAND_GET: u8 = 0;
+const COMMAND_SET: u8 = 1;
+const COMMAND_CURRENT: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownCommand(u8),
+    /// A record has a value that doesn't fit its key.
+    InvalidValue(u8),
+    BufferTooSmall,
+}
+
+/// Order in which the channels of a pixel are sent to the strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorOrder {
+    Rgb = 0,
+    Rbg = 1,
+    Grb = 2,
+    Gbr = 3,
+    Brg = 4,
+    Bgr = 5,
+}
+
+impl ColorOrder {
+    const ALL: [ColorOrder; 6] = [
+        ColorOrder::Rgb,
+        ColorOrder::Rbg,
+        ColorOrder::Grb,
+        ColorOrder::Gbr,
+        ColorOrder::Brg,
+        ColorOrder::Bgr,
+    ];
+
+    /// Parses an order like `"grb"`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|order| order.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorOrder::Rgb => "rgb",
+            ColorOrder::Rbg => "rbg",
+            ColorOrder::Grb => "grb",
+            ColorOrder::Gbr => "gbr",
+            ColorOrder::Brg => "brg",
+            ColorOrder::Bgr => "bgr",
+        }
+    }
+
+    /// Reorders an RGB pixel into the order the strip expects.
+    pub fn apply(self, [r, g, b]: [u8; 3]) -> [u8; 3] {
+        match self {
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Rbg => [r, b, g],
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Bgr => [b, g, r],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorOrder {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or(Error::InvalidValue(KEY_COLOR_ORDER))
+    }
+}
+
+/// Effect the firmware renders by itself while no frames arrive over the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalEffect {
+    Rainbow,
+    Solid([u8; 3]),
+    Off,
+}
+
+impl LocalEffect {
+    /// Parses an effect like `"rainbow"`, ignoring case. `color` is only used by `"solid"`.
+    pub fn from_name(name: &str, color: [u8; 3]) -> Option<Self> {
+        [
+            LocalEffect::Rainbow,
+            LocalEffect::Solid(color),
+            LocalEffect::Off,
+        ]
+        .into_iter()
+        .find(|effect| effect.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LocalEffect::Rainbow => "rainbow",
+            LocalEffect::Solid(_) => "solid",
+            LocalEffect::Off => "off",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Length of the strip.
+    pub leds: u16,
+    pub color_order: ColorOrder,
+    /// GPIO the strip's data line is connected to.
+    pub data_pin: u8,
+    /// Universe holding the first LED when receiving E1.31 or Art-Net, or `None` for the first
+    /// universe of the protocol.
+    pub start_universe: Option<u16>,
+    /// Channel (counting from zero) of the first LED in every universe.
+    pub start_channel: u16,
+    /// Channels used for LEDs in every universe, starting at `start_channel`.
+    pub channels_per_universe: u16,
+    pub local_effect: LocalEffect,
+    /// Whether to fade between received frames, smoothing over dropped or late ones at the cost
+    /// of a frame of latency.
+    pub interpolate: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            leds: 100,
+            color_order: ColorOrder::Rgb,
+            data_pin: 7,
+            start_universe: None,
+            start_channel: 0,
+            channels_per_universe: 510,
+            local_effect: LocalEffect::Rainbow,
+            interpolate: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Writes the settings records into `buf`, returning their length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        let mut record = |key: u8, value: &[u8]| {
+            let end = len + 2 + value.len();
+            let record = buf.get_mut(len..end).ok_or(Error::BufferTooSmall)?;
+
+            record[0] = key;
+            record[1] = value.len() as u8;
+            record[2..].copy_from_slice(value);
+            len = end;
+
+            Ok(())
+        };
+
+        record(KEY_LEDS, &self.leds.to_be_bytes())?;
+        record(KEY_COLOR_ORDER, &[self.color_order as u8])?;
+        record(KEY_DATA_PIN, &[self.data_pin])?;
+        if let Some(universe) = self.start_universe {
+            record(KEY_START_UNIVERSE, &universe.to_be_bytes())?;
+        }
+        record(KEY_START_CHANNEL, &self.start_channel.to_be_bytes())?;
+        record(
+            KEY_CHANNELS_PER_UNIVERSE,
+            &self.channels_per_universe.to_be_bytes(),
+        )?;
+        match self.local_effect {
+            LocalEffect::Rainbow => record(KEY_LOCAL_EFFECT, &[0])?,
+            LocalEffect::Solid([r, g, b]) => record(KEY_LOCAL_EFFECT, &[1, r, g, b])?,
+            LocalEffect::Off => record(KEY_LOCAL_EFFECT, &[2])?,
+        }
+        record(KEY_INTERPOLATE, &[self.interpolate as u8])?;
+
+        Ok(len)
+    }
+
+    /// Parses settings records, using defaults for settings that are missing and skipping unknown
+    /// ones.
+    pub fn decode(mut records: &[u8]) -> Result<Self, Error> {
+        let mut settings = Settings::default();
+
+        while let [key, len, ref rest @ ..] = *records {
+            let value = rest.get(..len as usize).ok_or(Error::TooShort)?;
+
+            match (key, value) {
+                (KEY_LEDS, &[high, low]) => settings.leds = u16::from_be_bytes([high, low]),
+                (KEY_COLOR_ORDER, &[order]) => settings.color_order = ColorOrder::try_from(order)?,
+                (KEY_DATA_PIN, &[pin]) => settings.data_pin = pin,
+                (KEY_START_UNIVERSE, &[high, low]) => {
+                    settings.start_universe = Some(u16::from_be_bytes([high, low]))
+                }
+                (KEY_START_CHANNEL, &[high, low]) => {
+                    settings.start_channel = u16::from_be_bytes([high, low])
+                }
+                (KEY_CHANNELS_PER_UNIVERSE, &[high, low]) => {
+                    settings.channels_per_universe = u16::from_be_bytes([high, low])
+                }
+                (KEY_LOCAL_EFFECT, &[0]) => settings.local_effect = LocalEffect::Rainbow,
+                (KEY_LOCAL_EFFECT, &[1, r, g, b]) => {
+                    settings.local_effect = LocalEffect::Solid([r, g, b])
+                }
+                (KEY_LOCAL_EFFECT, &[2]) => settings.local_effect = LocalEffect::Off,
+                (KEY_INTERPOLATE, &[interpolate @ (0 | 1)]) => {
+                    settings.interpolate = interpolate == 1
+                }
+                (
+                    KEY_LEDS
+                    | KEY_COLOR_ORDER
+                    | KEY_DATA_PIN
+                    | KEY_START_UNIVERSE
+                    | KEY_START_CHANNEL
+                    | KEY_CHANNELS_PER_UNIVERSE
+                    | KEY_LOCAL_EFFECT
+                    | KEY_INTERPOLATE,
+                    _,
+                ) => return Err(Error::InvalidValue(key)),
+                _ => {}
+            }
+
+            records = &rest[len as usize..];
+        }
+
+        if !records.is_empty() {
+            return Err(Error::TooShort);
+        }
+
+        // At least a pixel, all within a universe
+        let channels = settings.start_channel as usize + settings.channels_per_universe as usize;
+        if settings.channels_per_universe < 3 || channels > UNIVERSE_CHANNELS {
+            return Err(Error::InvalidValue(KEY_CHANNELS_PER_UNIVERSE));
+        }
+
+        Ok(settings)
+    }
+
+    /// How DMX universes map onto the strip, for a protocol whose universes start at
+    /// `first_universe`.
+    pub fn universe_map(&self, first_universe: u16) -> UniverseMap {
+        UniverseMap {
+            start_universe: self.start_universe.unwrap_or(first_universe),
+            start_channel: self.start_channel,
+            channels_per_universe: self.channels_per_universe,
+            format: PixelFormat::Rgb8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// Asks the device for its current settings.
+    Get,
+    /// Replaces the device's settings, which may restart to apply them.
+    Set(Settings),
+    /// The device's settings, sent in reply to either of the others.
+    Current(Settings),
+}
+
+impl Message {
+    /// Writes the message into `buf`, returning its length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let (command, settings) = match self {
+            Message::Get => (COMMAND_GET, None),
+            Message::Set(settings) => (COMMAND_SET, Some(settings)),
+            Message::Current(settings) => (COMMAND_CURRENT, Some(settings)),
+        };
+
+        let header = buf.get_mut(..HEADER_LEN).ok_or(Error::BufferTooSmall)?;
+        header[0..2].copy_from_slice(&MAGIC);
+        header[2] = VERSION;
+        header[3] = command;
+
+        let len = match settings {
+            Some(settings) => settings.encode(&mut buf[HEADER_LEN..])?,
+            None => 0,
+        };
+
+        Ok(HEADER_LEN + len)
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<Self, Error> {
+        if packet.len() < HEADER_LEN {
+            return Err(Error::TooShort);
+        }
+
+        if packet[0..2] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        if packet[2] != VERSION {
+            return Err(Error::UnsupportedVersion(packet[2]));
+        }
+
+        let records = &packet[HEADER_LEN..];
+        match packet[3] {
+            COMMAND_GET => Ok(Message::Get),
+            COMMAND_SET => Ok(Message::Set(Settings::decode(records)?)),
+            COMMAND_CURRENT => Ok(Message::Current(Settings::decode(records)?)),
+            command => Err(Error::UnknownCommand(command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_messages() {
+        let settings = Settings {
+            leds: 300,
+            color_order: ColorOrder::Grb,
+            data_pin: 10,
+            start_universe: Some(5),
+            start_channel: 3,
+            channels_per_universe: 300,
+            local_effect: LocalEffect::Solid([1, 2, 3]),
+            interpolate: true,
+        };
+        let mut buf = [0; MAX_MESSAGE_LEN];
+
+        for message in [
+            Message::Get,
+            Message::Set(settings),
+            Message::Current(settings),
+        ] {
+            let len = message.encode(&mut buf).unwrap();
+            assert_eq!(Message::decode(&buf[..len]), Ok(message));
+        }
+    }
+
+    #[test]
+    fn decodes_partial_and_unknown_records() {
+        assert_eq!(Settings::decode(&[]), Ok(Settings::default()));
+
+        assert_eq!(
+            Settings::decode(&[9, 3, 1, 2, 3, KEY_DATA_PIN, 1, 4]),
+            Ok(Settings {
+                data_pin: 4,
+                ..Settings::default()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_records() {
+        assert_eq!(
+            Settings::decode(&[KEY_LEDS, 1, 5]),
+            Err(Error::InvalidValue(KEY_LEDS))
+        );
+        assert_eq!(
+            Settings::decode(&[KEY_COLOR_ORDER, 1, 6]),
+            Err(Error::InvalidValue(KEY_COLOR_ORDER))
+        );
+        assert_eq!(Settings::decode(&[KEY_LEDS, 2, 5]), Err(Error::TooShort));
+        assert_eq!(
+            Settings::decode(&[
+                KEY_START_CHANNEL,
+                2,
+                0,
+                100,
+                KEY_CHANNELS_PER_UNIVERSE,
+                2,
+                1,
+                200
+            ]),
+            Err(Error::InvalidValue(KEY_CHANNELS_PER_UNIVERSE))
+        );
+
+        assert_eq!(
+            Settings::decode(&[KEY_LOCAL_EFFECT, 1, 1]),
+            Err(Error::InvalidValue(KEY_LOCAL_EFFECT))
+        );
+        assert_eq!(
+            Settings::decode(&[KEY_INTERPOLATE, 1, 2]),
+            Err(Error::InvalidValue(KEY_INTERPOLATE))
+        );
+
+        // Erased flash
+        assert_eq!(Settings::decode(&[0xff; 16]), Err(Error::TooShort));
+    }
+
+    #[test]
+    fn maps_universes() {
+        let settings = Settings::default();
+        assert_eq!(settings.universe_map(1), UniverseMap::new(1));
+
+        let settings = Settings {
+            start_universe: Some(10),
+            start_channel: 1,
+            ..Settings::default()
+        };
+        let map = settings.universe_map(0);
+        assert_eq!(map.start_universe, 10);
+        assert_eq!(map.start_channel, 1);
+    }
+
+    #[test]
+    fn parses_local_effects() {
+        assert_eq!(
+            LocalEffect::from_name("Solid", [1, 2, 3]),
+            Some(LocalEffect::Solid([1, 2, 3]))
+        );
+        assert_eq!(
+            LocalEffect::from_name("off", [1, 2, 3]),
+            Some(LocalEffect::Off)
+        );
+        assert_eq!(LocalEffect::from_name("wave", [1, 2, 3]), None);
+    }
+
+    #[test]
+    fn reorders_channels() {
+        assert_eq!(ColorOrder::from_name("GRB"), Some(ColorOrder::Grb));
+        assert_eq!(ColorOrder::from_name("rgbw"), None);
+        assert_eq!(ColorOrder::Grb.apply([1, 2, 3]), [2, 1, 3]);
+        assert_eq!(ColorOrder::Brg.apply([1, 2, 3]), [3, 1, 2]);
+    }
+}
@@ -0,0 +1,179 @@
+//! WLED's UDP realtime protocol, as sent by Hyperion and various phone apps.
+//!
+//! Every packet starts with a byte selecting the format of the pixel data and a byte giving the
+//! number of seconds to keep showing it for, after which the device returns to its own effects.
+
+use crate::{PixelFormat, Pixels};
+
+/// UDP port WLED listens on for realtime data.
+pub const PORT: u16 = 21324;
+
+/// Timeout meaning the received data is shown until other data arrives.
+pub const TIMEOUT_NEVER: u8 = 255;
+
+pub const HEADER_LEN: usize = 2;
+
+/// Most RGB pixels that fit in a DNRGB packet.
+pub const MAX_DNRGB_PIXELS: usize = 489;
+
+pub const DNRGB_HEADER_LEN: usize = HEADER_LEN + 2;
+
+/// Pairs of LED index and RGB.
+const MODE_WARLS: u8 = 1;
+/// RGB for LEDs from the start of the strip.
+const MODE_DRGB: u8 = 2;
+/// RGBW for LEDs from the start of the strip.
+const MODE_DRGBW: u8 = 3;
+/// RGB for LEDs from a 16 bit start index.
+const MODE_DNRGB: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooShort,
+    /// The packet is a WLED sync notification or something newer.
+    UnsupportedMode(u8),
+    BufferTooSmall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    /// Seconds to show the data for, or [`TIMEOUT_NEVER`].
+    pub timeout: u8,
+    pub pixels: Pixels<'a>,
+}
+
+impl<'a> Packet<'a> {
+    pub fn decode(packet: &'a [u8]) -> Result<Self, Error> {
+        let [mode, timeout, ref data @ ..] = *packet else {
+            return Err(Error::TooShort);
+        };
+
+        let pixels = match mode {
+            MODE_WARLS => Pixels::Indexed(data),
+            MODE_DRGB => Pixels::Range {
+                offset: 0,
+                format: PixelFormat::Rgb8,
+                data,
+            },
+            MODE_DRGBW => Pixels::Range {
+                offset: 0,
+                format: PixelFormat::Rgbw8,
+                data,
+            },
+            MODE_DNRGB => {
+                let [high, low, ref data @ ..] = *data else {
+                    return Err(Error::TooShort);
+                };
+
+                Pixels::Range {
+                    offset: u16::from_be_bytes([high, low]) as usize,
+                    format: PixelFormat::Rgb8,
+                    data,
+                }
+            }
+            mode => return Err(Error::UnsupportedMode(mode)),
+        };
+
+        Ok(Packet { timeout, pixels })
+    }
+
+    /// Seconds to show the data for, or `None` if it should be shown until other data arrives.
+    pub fn timeout_secs(&self) -> Option<u8> {
+        (self.timeout != TIMEOUT_NEVER).then_some(self.timeout)
+    }
+}
+
+/// Encodes a DNRGB packet for RGB `pixels` starting at LED `offset` into `buf`, returning the
+/// packet length.
+pub fn encode_dnrgb(
+    timeout: u8,
+    offset: u16,
+    pixels: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let len = DNRGB_HEADER_LEN + pixels.len();
+    if buf.len() < len {
+        return Err(Error::BufferTooSmall);
+    }
+
+    buf[0] = MODE_DNRGB;
+    buf[1] = timeout;
+    buf[2..4].copy_from_slice(&offset.to_be_bytes());
+    buf[DNRGB_HEADER_LEN..len].copy_from_slice(pixels);
+
+    Ok(len)
+}
+
+/// Splits RGB pixel data for a whole strip into the offsets and data of DNRGB packets.
+pub fn dnrgb_fragments(pixels: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    pixels
+        .chunks(MAX_DNRGB_PIXELS * 3)
+        .enumerate()
+        .map(|(i, chunk)| ((i * MAX_DNRGB_PIXELS) as u16, chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn decodes_every_mode() {
+        let warls = Packet::decode(&[1, 5, 3, 10, 20, 30, 0, 1, 2, 3]).unwrap();
+        assert_eq!(warls.timeout_secs(), Some(5));
+        assert_eq!(
+            warls.pixels.iter().collect::<Vec<_>>(),
+            [(3, [10, 20, 30]), (0, [1, 2, 3])]
+        );
+
+        let drgb = Packet::decode(&[2, 255, 1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(drgb.timeout_secs(), None);
+        assert_eq!(
+            drgb.pixels.iter().collect::<Vec<_>>(),
+            [(0, [1, 2, 3]), (1, [4, 5, 6])]
+        );
+
+        let drgbw = Packet::decode(&[3, 1, 10, 20, 30, 5]).unwrap();
+        assert_eq!(drgbw.pixels.iter().collect::<Vec<_>>(), [(0, [15, 25, 35])]);
+
+        let dnrgb = Packet::decode(&[4, 1, 0x01, 0x02, 1, 2, 3]).unwrap();
+        assert_eq!(dnrgb.pixels.iter().collect::<Vec<_>>(), [(258, [1, 2, 3])]);
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        assert_eq!(Packet::decode(&[2]), Err(Error::TooShort));
+        assert_eq!(Packet::decode(&[4, 1, 0]), Err(Error::TooShort));
+        assert_eq!(
+            Packet::decode(&[0, 1, 2, 3]),
+            Err(Error::UnsupportedMode(0))
+        );
+    }
+
+    #[test]
+    fn dnrgb_round_trip() {
+        let pixels = (0..1000 * 3).map(|i| i as u8).collect::<Vec<_>>();
+        let mut buf = [0; 1472];
+
+        let fragments = dnrgb_fragments(&pixels).collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 3);
+
+        let mut decoded = Vec::new();
+        for (offset, data) in fragments {
+            let len = encode_dnrgb(2, offset, data, &mut buf).unwrap();
+            assert!(len <= 1472);
+
+            let packet = Packet::decode(&buf[..len]).unwrap();
+            assert_eq!(packet.timeout_secs(), Some(2));
+            decoded.extend(packet.pixels.iter());
+        }
+
+        assert_eq!(decoded.len(), 1000);
+        assert!(decoded
+            .iter()
+            .enumerate()
+            .all(|(i, &(index, rgb))| index == i
+                && rgb == [pixels[i * 3], pixels[i * 3 + 1], pixels[i * 3 + 2]]));
+    }
+}